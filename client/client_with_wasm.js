@@ -3,7 +3,17 @@ const BALL_RADIUS = 15;
 const AVATAR_RADIUS = 20;
 const BALL_FRICTION = 0.96;
 const KICK_POWER = 4.0;
-const PLAYER_SPEED = 120; // pixels per second
+const PLAYER_SPEED = 120; // pixels per second (сервер ограничивает Move этой скоростью)
+const DEFAULT_ROOM_SIZE = 8; // max_players для комнаты, которую клиент создаёт сам
+
+// Handshake (совпадает с york-protocol/src/lib.rs)
+const PROTOCOL_VERSION = 1;
+const CLIENT_BUILD = "york-web/0.1.0";
+
+// Ворота (совпадают с game_server/src/match_state.rs)
+const GOAL_TOP = 225;
+const GOAL_BOTTOM = 375;
+const GOAL_DEPTH = 40;
 
 // Fixed timestep for game logic (30 FPS, like Flash)
 const FIXED_TIMESTEP = 1000 / 30; // ~33.33ms
@@ -12,10 +22,67 @@ const SMOOTHING_FACTOR = 0.2; // Степень сглаживания для и
 // Global variables for WASM module
 let wasmModule = null;
 let wasmReady = false;
+let snapshotDecoder = null; // Восстанавливает полное состояние из Snapshot/Delta
+// Буферы для snapshotDecoder.decode_into: id и x, y, vel_x, vel_y каждой сущности
+const MAX_SNAPSHOT_ENTITIES = 64; // Мяч + игроки, с запасом над YORK_MAX_ROOM_PLAYERS (10)
+const snapshotIds = new Uint32Array(MAX_SNAPSHOT_ENTITIES);
+const snapshotFields = new Float64Array(MAX_SNAPSHOT_ENTITIES * 4);
+const BALL_ENTITY_ID = 0xffffffff;
+const MATCH_PHASE_PLAYING = 1; // MatchPhase::Playing
+
+// Поля сообщений в порядке york-protocol (src/client.rs, src/server.rs).
+// Формат описан в york-ball-game/src/schema.rs; Snapshot/Delta с wasm
+// разбирает SnapshotDecoder, схема Snapshot нужна только msgpack-lite.
+// Таблицу сверяет с york-protocol тест client_schemas_match_the_protocol
+const MESSAGE_SCHEMAS = {
+    // Клиент -> сервер
+    Hello: ["protocol_version:u32", "client_build:string", { name: "capabilities", list: "string" }],
+    Move: ["x:f64", "y:f64", "vel_x:f64", "vel_y:f64"],
+    Kick: ["x:f64", "y:f64", "dir_x:f64", "dir_y:f64"],
+    Ack: ["tick:u32"],
+    CreateRoom: ["name:string", "max_players:u32"],
+    JoinRoom: ["room_id:u32"],
+    LeaveRoom: [],
+    ListRooms: [],
+    QueueForMatch: [],
+    StartMatch: [],
+    // Сервер -> клиент
+    Welcome: ["protocol_version:u32", "id:u32", { name: "capabilities", list: "string" }],
+    VersionMismatch: ["server_version:u32", "client_version:u32"],
+    Joined: ["id:u32"],
+    Left: ["id:u32"],
+    Correction: ["x:f64", "y:f64", "vel_x:f64", "vel_y:f64"],
+    Snapshot: ["tick:u32", "ball:f64[4]", { name: "players", list: ["id:u32", "x:f64", "y:f64", "vel_x:f64", "vel_y:f64"] }],
+    RoomJoined: ["room_id:u32", "name:string", "max_players:u32"],
+    RoomLeft: ["room_id:u32"],
+    RoomList: [{ name: "rooms", list: ["id:u32", "name:string", "players:u32", "max_players:u32"] }],
+    RoomError: ["reason:string"],
+    Warning: ["reason:string"],
+    MatchFound: ["room_id:u32", "team:u8"],
+    GoalScored: ["team:u8", "scorer_id:u32?", "score:u32[2]"],
+    Kickoff: [{ name: "positions", list: ["id:u32", "x:f64", "y:f64"] }],
+    MatchState: ["phase:u8", "left_side:u8", "score:u32[2]", { name: "teams", list: ["id:u32", "team:u8"] }],
+    Clock: ["half:u32", "remaining:u32"],
+    MatchEnded: ["score:u32[2]", "winner:u8?", { name: "stats", list: ["id:u32", "team:u8", "goals:u32", "kicks:u32", "touches:u32"] }]
+};
 
 // Game state
 let socket = null;
 let playerId = null;
+let serverCapabilities = []; // Возможности, согласованные в Welcome
+let outbox = []; // Сообщения серверу, отправляемые одним кадром раз в fixedUpdate
+let pendingAckTick = null; // Последний снимок для Ack в следующем кадре outbox
+let connectionError = null; // Причина отказа сервера (VersionMismatch)
+let currentRoom = null; // { id, name, maxPlayers } после RoomJoined
+let myTeam = null; // 0 - левая, 1 - правая (после MatchFound)
+let queuedForMatch = false;
+let score = [0, 0]; // Голы левой и правой команды
+let playerTeams = {}; // id -> 0/1 из MatchState
+let lastGoal = null; // { team, scorerId, time } для надписи после гола
+let matchPlaying = false; // false - лобби между матчами
+let leftSide = 0; // Команда, защищающая левые ворота (меняется в перерыве)
+let clock = null; // { half, remaining } из Clock
+let matchResult = null; // Последний MatchEnded для таблицы итогов
 let players = {}; // Теперь содержит logical и visual позиции
 let ball = {
     logical: { x: 400, y: 300, vx: 0, vy: 0 },
@@ -74,10 +141,18 @@ tempCtx.beginPath();
 tempCtx.moveTo(400, 50);
 tempCtx.lineTo(400, 550); // Middle line
 tempCtx.stroke();
+// Goals on the left and right edges
+tempCtx.fillStyle = "rgba(255, 255, 255, 0.5)";
+tempCtx.fillRect(0, GOAL_TOP, GOAL_DEPTH, GOAL_BOTTOM - GOAL_TOP);
+tempCtx.fillRect(800 - GOAL_DEPTH, GOAL_TOP, GOAL_DEPTH, GOAL_BOTTOM - GOAL_TOP);
 fieldImg.src = tempCanvas.toDataURL();
 
 const ballImg = createPlaceholderImage("#FFFFFF", BALL_RADIUS * 2); // White ball
 const avatarImg = createPlaceholderImage("#3498DB", AVATAR_RADIUS * 2); // Blue avatar
+const teamAvatarImgs = [
+    createPlaceholderImage("#E74C3C", AVATAR_RADIUS * 2), // Left team - red
+    createPlaceholderImage("#3498DB", AVATAR_RADIUS * 2)  // Right team - blue
+];
 
 // Initialize game
 async function init() {
@@ -87,6 +162,10 @@ async function init() {
         const wasm = await import('./wasm/msgpack_wasm.js');
         // Initialize the module
         wasmModule = await wasm.default();
+        snapshotDecoder = new wasm.SnapshotDecoder();
+        for (const [name, fields] of Object.entries(MESSAGE_SCHEMAS)) {
+            wasmModule.define_schema(name, fields);
+        }
         wasmReady = true;
         console.log("WebAssembly MessagePack module loaded successfully");
     } catch (error) {
@@ -108,6 +187,7 @@ function connectToServer() {
     // WebSocket event handlers
     socket.onopen = () => {
         console.log("Connected to server");
+        sendHello();
     };
     
     socket.onclose = () => {
@@ -119,41 +199,26 @@ function connectToServer() {
     };
     
     socket.onmessage = (event) => {
+        const rawData = new Uint8Array(event.data);
+        if (!wasmReady) {
+            handleRawMessage(rawData);
+            return;
+        }
+        // С batching сервер присылает несколько сообщений в одном кадре,
+        // большие кадры - в LZ4-конверте (split_batch его снимает)
+        let messages;
         try {
-            // Use WebAssembly for decoding if available
-            const rawData = new Uint8Array(event.data);
-            debugLog("Raw message bytes:", Array.from(rawData.slice(0, 20)));
-            
-            let parsedData;
-            if (wasmReady) {
-                // Use our WebAssembly decoder
-                parsedData = wasmModule.decode_array_message(rawData);
-                // Convert array format to object format for compatibility
-                if (Array.isArray(parsedData)) {
-                    parsedData = convertArrayToObject(parsedData);
-                }
-            } else {
-                // Fallback to msgpack-lite
-                parsedData = msgpack.decode(rawData);
-                // If data is an array, convert it to object
-                if (Array.isArray(parsedData)) {
-                    parsedData = convertArrayToObject(parsedData);
-                }
-            }
-            
-            debugLog("Decoded message:", parsedData);
-            handleMessage(parsedData);
+            messages = wasmModule.split_batch(rawData);
         } catch (e) {
-            console.error("Error processing message:", e);
-            if (event.data instanceof ArrayBuffer) {
-                const bytes = new Uint8Array(event.data);
-                console.error("Raw bytes:", Array.from(bytes));
-            }
+            console.error("Error splitting batch:", e, Array.from(rawData));
+            return;
         }
+        messages.forEach(handleRawMessage);
     };
     
     // Set up game input
     canvas.addEventListener("mousedown", handleMouseDown);
+    window.addEventListener("keydown", handleKeyDown);
     
     // Start render loop (high frequency, variable timestep)
     requestAnimationFrame(renderLoop);
@@ -163,50 +228,189 @@ function connectToServer() {
     fixedUpdateInterval = setInterval(fixedUpdate, FIXED_TIMESTEP);
 }
 
-// Helper function to convert array format messages to object format
-function convertArrayToObject(data) {
-    if (!Array.isArray(data) || data.length < 1) {
-        return data;
+// Decode and handle one server message
+function handleRawMessage(rawData) {
+    try {
+        // Use WebAssembly for decoding if available
+        debugLog("Raw message bytes:", Array.from(rawData.slice(0, 20)));
+        
+        let parsedData;
+        if (wasmReady) {
+            const messageType = wasmModule.peek_message_type(rawData);
+            if (messageType === "Snapshot" || messageType === "Delta") {
+                // Дельты применяются к подтверждённому снимку внутри wasm
+                const count = snapshotDecoder.decode_into(rawData, snapshotIds, snapshotFields);
+                sendAck(snapshotDecoder.tick);
+                applySnapshotBuffers(count);
+                return;
+            }
+            
+            // Use our WebAssembly decoder
+            parsedData = wasmModule.decode_message(rawData);
+        } else {
+            // Fallback to msgpack-lite
+            parsedData = msgpack.decode(rawData);
+            if (Array.isArray(parsedData)) {
+                parsedData = unflattenMessage(parsedData);
+            }
+        }
+        
+        debugLog("Decoded message:", parsedData);
+        handleMessage(parsedData);
+    } catch (e) {
+        console.error("Error processing message:", e);
+        console.error("Raw bytes:", Array.from(rawData));
+    }
+}
+
+// Send a client message; `fields` are named as in MESSAGE_SCHEMAS
+function sendClientMessage(messageType, fields) {
+    if (!socket || socket.readyState !== WebSocket.OPEN) return;
+    queueMessage(encodeClientMessage(messageType, fields));
+}
+
+function encodeClientMessage(messageType, fields) {
+    if (wasmReady) {
+        // Проверяет типы и диапазоны полей по схеме
+        return wasmModule.encode_message(messageType, fields);
     }
-    
-    const messageType = data[0];
-    
-    if (messageType === "Init" && data.length > 1) {
-        return {
-            type: messageType,
-            id: data[1]
-        };
-    } else if (messageType === "Joined" && data.length > 1) {
-        return {
-            type: messageType,
-            id: data[1]
-        };
-    } else if (messageType === "PlayerMove" && data.length > 5) {
-        return {
-            type: messageType,
-            id: data[1],
-            x: data[2],
-            y: data[3],
-            vel_x: data[4],
-            vel_y: data[5]
-        };
-    } else if (messageType === "Kick" && data.length > 5) {
-        return {
-            type: messageType,
-            id: data[1],
-            x: data[2],
-            y: data[3],
-            dirX: data[4],
-            dirY: data[5]
-        };
-    } else if (messageType === "Left" && data.length > 1) {
-        return {
-            type: messageType,
-            id: data[1]
-        };
+    return msgpack.encode(flattenMessage(messageType, fields));
+}
+
+// Messages are sent together at the end of the next fixedUpdate; the server
+// splits frames into messages, so several may share one frame
+function queueMessage(encodedMsg) {
+    outbox.push(encodedMsg);
+}
+
+function flushOutbox() {
+    if (!socket || socket.readyState !== WebSocket.OPEN) return;
+    if (pendingAckTick !== null) {
+        outbox.push(encodeClientMessage("Ack", { tick: pendingAckTick }));
+        pendingAckTick = null;
     }
+    if (outbox.length === 0) return;
     
-    return data;
+    const frame = new Uint8Array(outbox.reduce((size, msg) => size + msg.length, 0));
+    let offset = 0;
+    for (const msg of outbox) {
+        frame.set(msg, offset);
+        offset += msg.length;
+    }
+    outbox = [];
+    socket.send(frame);
+}
+
+// First message on the connection; capabilities are what this client can decode
+function sendHello() {
+    // Дельты и i16-координаты восстанавливает только SnapshotDecoder из wasm,
+    // кадры делит и распаковывает из LZ4 split_batch; float32 понимает и msgpack-lite
+    const capabilities = wasmReady
+        ? ["delta_snapshots", "batching", "f32_positions", "quantized_positions", "lz4"]
+        : ["f32_positions"];
+    socket.send(encodeClientMessage("Hello", {
+        protocol_version: PROTOCOL_VERSION,
+        client_build: CLIENT_BUILD,
+        capabilities: capabilities
+    }));
+}
+
+// Move/Kick go as float32 once the server agreed on compact positions
+function applyPositionEncoding(capabilities) {
+    if (!wasmReady) return;
+    const compact = capabilities.includes("f32_positions") || capabilities.includes("quantized_positions");
+    const type = compact ? "f32" : "f64";
+    wasmModule.define_schema("Move", ["x", "y", "vel_x", "vel_y"].map(name => `${name}:${type}`));
+    wasmModule.define_schema("Kick", ["x", "y", "dir_x", "dir_y"].map(name => `${name}:${type}`));
+}
+
+// Confirm a decoded snapshot so the server can send deltas against it; only
+// the newest tick is sent, once per outbox frame, whatever the server tick rate
+function sendAck(tick) {
+    if (!serverCapabilities.includes("delta_snapshots")) return;
+    pendingAckTick = tick;
+}
+
+// Same positional layout as york-ball-game/src/schema.rs, for msgpack-lite
+// when wasm failed to load (no validation here)
+function tupleLength(type) {
+    const match = /\[(\d+)\]/.exec(type);
+    return match ? Number(match[1]) : 0;
+}
+
+function flattenMessage(messageType, fields) {
+    const out = [messageType];
+    flattenFields(MESSAGE_SCHEMAS[messageType], fields, out);
+    return out;
+}
+
+function flattenFields(schema, obj, out) {
+    for (const field of schema) {
+        if (typeof field === "string") {
+            const [name, type] = field.split(":");
+            flattenValue(type, obj[name], out);
+            continue;
+        }
+        const items = obj[field.name];
+        out.push(items.length);
+        for (const item of items) {
+            if (typeof field.list === "string") {
+                flattenValue(field.list, item, out);
+            } else {
+                flattenFields(field.list, item, out);
+            }
+        }
+    }
+}
+
+function flattenValue(type, value, out) {
+    const len = tupleLength(type);
+    if (len) {
+        out.push(...(value ?? new Array(len).fill(null)));
+    } else {
+        out.push(value ?? null);
+    }
+}
+
+function unflattenMessage(data) {
+    const schema = MESSAGE_SCHEMAS[data[0]];
+    if (!schema) return data;
+    const message = { type: data[0] };
+    unflattenFields(schema, data, 1, message);
+    return message;
+}
+
+// Returns the position after the fields read into `obj`
+function unflattenFields(schema, data, pos, obj) {
+    for (const field of schema) {
+        if (typeof field === "string") {
+            const [name, type] = field.split(":");
+            [obj[name], pos] = unflattenValue(type, data, pos);
+            continue;
+        }
+        const items = [];
+        const count = data[pos++];
+        for (let i = 0; i < count; i++) {
+            if (typeof field.list === "string") {
+                let item;
+                [item, pos] = unflattenValue(field.list, data, pos);
+                items.push(item);
+            } else {
+                const item = {};
+                pos = unflattenFields(field.list, data, pos, item);
+                items.push(item);
+            }
+        }
+        obj[field.name] = items;
+    }
+    return pos;
+}
+
+function unflattenValue(type, data, pos) {
+    const len = tupleLength(type);
+    if (!len) return [data[pos] ?? null, pos + 1];
+    const values = data.slice(pos, pos + len);
+    return [values.every(value => value === null) ? null : values, pos + len];
 }
 
 // Fixed timestep update function (runs at exactly 30 FPS)
@@ -220,6 +424,7 @@ function fixedUpdate() {
     // Update ball physics with fixed timestep
     updateBall(fixedDelta);
     
+    flushOutbox();
     lastFixedUpdateTime = now;
 }
 
@@ -258,8 +463,8 @@ function interpolatePositions() {
 function handleMessage(data) {
     debugLog("Processing message:", data);
     
-    // Проверка ID на валидность для всех типов сообщений кроме "Init"
-    if (data.type !== "Init" && data.id !== undefined) {
+    // Проверка ID на валидность для всех типов сообщений кроме "Welcome"
+    if (data.type !== "Welcome" && data.id !== undefined) {
         // Убедимся, что ID у нас в виде числа
         if (typeof data.id !== 'number') {
             data.id = Number(data.id);
@@ -271,7 +476,14 @@ function handleMessage(data) {
     }
 
     switch (data.type) {
-        case "Init":
+        case "VersionMismatch":
+            connectionError = `Server speaks protocol v${data.server_version}, this client v${data.client_version}. Please reload the page.`;
+            console.error(connectionError);
+            break;
+            
+        case "Welcome":
+            serverCapabilities = data.capabilities;
+            applyPositionEncoding(serverCapabilities);
             // ИСПРАВЛЕНО: Убедимся, что ID извлекается правильно
             playerId = data.id;
             
@@ -280,7 +492,7 @@ function handleMessage(data) {
             
             // Проверка на валидность ID
             if (playerId === undefined || playerId === null) {
-                console.error("Received Init message with invalid ID", data);
+                console.error("Received Welcome message with invalid ID", data);
                 return;
             }
             
@@ -292,6 +504,107 @@ function handleMessage(data) {
             ball.logical = { x: 400, y: 300, vx: 0, vy: 0 };
             ball.visual = { x: 400, y: 300 };
             console.log(`Initialized as player ${playerId}`, players[playerId]);
+            
+            // Игра идёт только внутри комнаты - выбираем её по списку
+            sendClientMessage("ListRooms", {});
+            break;
+            
+        case "RoomList":
+            if (currentRoom) break;
+            
+            const freeRoom = data.rooms.find(room => room.players < room.max_players);
+            if (freeRoom) {
+                sendClientMessage("JoinRoom", { room_id: freeRoom.id });
+            } else {
+                sendClientMessage("CreateRoom", { name: `room-${playerId}`, max_players: DEFAULT_ROOM_SIZE });
+            }
+            break;
+            
+        case "RoomJoined":
+            currentRoom = { id: data.room_id, name: data.name, maxPlayers: data.max_players };
+            myTeam = null;
+            score = [0, 0];
+            playerTeams = {};
+            matchPlaying = false;
+            leftSide = 0;
+            clock = null;
+            matchResult = null;
+            console.log(`Joined room ${data.name} (${data.room_id})`);
+            
+            // Игроки и мяч из прошлой комнаты больше не актуальны
+            for (let id in players) {
+                if (id != playerId) delete players[id];
+            }
+            ball.logical = { x: 400, y: 300, vx: 0, vy: 0 };
+            ball.visual = { x: 400, y: 300 };
+            if (snapshotDecoder) snapshotDecoder.reset();
+            break;
+            
+        case "RoomLeft":
+            console.log(`Left room ${data.room_id}`);
+            currentRoom = null;
+            for (let id in players) {
+                if (id != playerId) delete players[id];
+            }
+            break;
+            
+        case "MatchFound":
+            queuedForMatch = false;
+            myTeam = data.team;
+            console.log(`Match found in room ${data.room_id}, team ${data.team}`);
+            break;
+            
+        case "MatchState": {
+            const playing = data.phase === MATCH_PHASE_PLAYING;
+            if (playing && !matchPlaying) matchResult = null;
+            matchPlaying = playing;
+            leftSide = data.left_side;
+            if (!matchPlaying) clock = null;
+            score = data.score;
+            playerTeams = {};
+            for (const entry of data.teams) {
+                playerTeams[entry.id] = entry.team;
+            }
+            if (playerTeams[playerId] !== undefined) myTeam = playerTeams[playerId];
+            break;
+        }
+            
+        case "Clock":
+            clock = { half: data.half, remaining: data.remaining };
+            break;
+            
+        case "MatchEnded":
+            matchResult = data;
+            clock = null;
+            console.log(`Match ended ${data.score[0]}:${data.score[1]}, winner: ${data.winner}`);
+            break;
+            
+        case "GoalScored":
+            score = data.score;
+            lastGoal = { team: data.team, scorerId: data.scorer_id, time: Date.now() };
+            console.log(`Goal for team ${data.team} by ${data.scorer_id}, score ${score[0]}:${score[1]}`);
+            break;
+            
+        case "Kickoff":
+            // Сервер расставляет всех по стартовым позициям, включая нашего игрока
+            ball.logical = { x: 400, y: 300, vx: 0, vy: 0 };
+            ball.visual = { x: 400, y: 300 };
+            for (const pos of data.positions) {
+                const p = players[pos.id];
+                if (!p) continue;
+                p.logical = { x: pos.x, y: pos.y, vel_x: 0, vel_y: 0 };
+                p.visual = { x: pos.x, y: pos.y };
+            }
+            lastClickTarget = { x: null, y: null };
+            break;
+            
+        case "RoomError":
+            console.warn("Room request failed:", data.reason);
+            break;
+            
+        case "Warning":
+            // Например, слишком частые сообщения: дальше сервер закроет соединение
+            console.warn("Server warning:", data.reason);
             break;
             
         case "Joined":
@@ -314,61 +627,18 @@ function handleMessage(data) {
             }
             break;
             
-        case "PlayerMove":
-            // Update other player's movement vector
-            if (data.id !== undefined && data.id !== playerId) {
-                if (!players[data.id]) {
-                    // Create player if doesn't exist yet
-                    players[data.id] = { 
-                        logical: { 
-                            x: data.x || 100, 
-                            y: data.y || 100, 
-                            vel_x: data.vel_x || 0, 
-                            vel_y: data.vel_y || 0 
-                        },
-                        visual: { x: data.x || 100, y: data.y || 100 }
-                    };
-                    debugLog(`Created player ${data.id} from PlayerMove`, players[data.id]);
-                } else {
-                    // Update player logical position and velocity
-                    if (typeof data.x === 'number') players[data.id].logical.x = data.x;
-                    if (typeof data.y === 'number') players[data.id].logical.y = data.y;
-                    if (typeof data.vel_x === 'number') players[data.id].logical.vel_x = data.vel_x;
-                    if (typeof data.vel_y === 'number') players[data.id].logical.vel_y = data.vel_y;
-                    debugLog(`Updated player ${data.id} from PlayerMove`, players[data.id]);
-                }
+        case "Correction":
+            // Сервер не принял наш Move (скорость, границы поля) - берём его позицию
+            if (players[playerId]) {
+                players[playerId].logical = { x: data.x, y: data.y, vel_x: data.vel_x, vel_y: data.vel_y };
             }
             break;
             
-        case "Kick":
-            // ВАЖНО: Лучшая обработка удара по мячу от другого игрока
-            debugLog("Received Kick message:", data);
-            
-            // Применяем новые координаты мяча
-            if (typeof data.x === "number") ball.logical.x = data.x;
-            if (typeof data.y === "number") ball.logical.y = data.y;
-            
-            // Получаем направление удара (поддержка обоих форматов)
-            let dirX = 0, dirY = 0;
-            
-            // Поддержка обоих вариантов имен свойств
-            if (typeof data.dirX === 'number') dirX = data.dirX;
-            else if (typeof data.dir_x === 'number') dirX = data.dir_x;
-            
-            if (typeof data.dirY === 'number') dirY = data.dirY;
-            else if (typeof data.dir_y === 'number') dirY = data.dir_y;
-            
-            debugLog("Kick direction:", dirX, dirY);
-            
-            // Применяем скорость к мячу
-            const norm = Math.hypot(dirX, dirY);
-            if (norm > 0) {
-                const normalizedDirX = dirX / norm;
-                const normalizedDirY = dirY / norm;
-                ball.logical.vx = normalizedDirX * KICK_POWER;
-                ball.logical.vy = normalizedDirY * KICK_POWER;
-                
-                debugLog("Applied velocity to ball:", ball.logical.vx, ball.logical.vy);
+        case "Snapshot":
+            // Авторитетное состояние мира от сервера (приходит каждый тик)
+            applyBallState(...data.ball);
+            for (const state of data.players) {
+                applyPlayerState(state.id, state.x, state.y, state.vel_x, state.vel_y);
             }
             break;
             
@@ -378,6 +648,66 @@ function handleMessage(data) {
     }
 }
 
+// Snapshot decoded by the wasm fast path into snapshotIds/snapshotFields
+function applySnapshotBuffers(count) {
+    for (let i = 0; i < count; i++) {
+        const f = i * 4;
+        const x = snapshotFields[f], y = snapshotFields[f + 1];
+        const velX = snapshotFields[f + 2], velY = snapshotFields[f + 3];
+        if (snapshotIds[i] === BALL_ENTITY_ID) {
+            applyBallState(x, y, velX, velY);
+        } else {
+            applyPlayerState(snapshotIds[i], x, y, velX, velY);
+        }
+    }
+}
+
+function applyBallState(x, y, velX, velY) {
+    ball.logical.x = x;
+    ball.logical.y = y;
+    ball.logical.vx = velX;
+    ball.logical.vy = velY;
+}
+
+function applyPlayerState(id, x, y, velX, velY) {
+    // Своего игрока клиент двигает сам
+    if (id === playerId) return;
+    
+    if (!players[id]) {
+        players[id] = {
+            logical: { x: x, y: y, vel_x: velX, vel_y: velY },
+            visual: { x: x, y: y }
+        };
+        debugLog(`Created player ${id} from Snapshot`, players[id]);
+    } else {
+        const logical = players[id].logical;
+        logical.x = x;
+        logical.y = y;
+        logical.vel_x = velX;
+        logical.vel_y = velY;
+    }
+}
+
+// Handle keyboard shortcuts
+function handleKeyDown(e) {
+    // Q - встать в очередь матчмейкинга
+    if (e.key === "q" || e.key === "Q") {
+        if (!queuedForMatch && playerId !== null) {
+            queuedForMatch = true;
+            currentRoom = null;
+            sendClientMessage("QueueForMatch", {});
+            console.log("Queued for a match");
+        }
+    }
+    
+    // S - начать матч в текущей комнате
+    if (e.key === "s" || e.key === "S") {
+        if (currentRoom && !matchPlaying) {
+            sendClientMessage("StartMatch", {});
+        }
+    }
+}
+
 // Handle mouse clicks
 function handleMouseDown(e) {
     const rect = canvas.getBoundingClientRect();
@@ -414,28 +744,14 @@ function handleMouseDown(e) {
         lastClickTarget = { x, y };
         
         // Send move message to server with current position and velocity vector
-        if (socket && socket.readyState === WebSocket.OPEN) {
-            const moveMsg = { 
-                type: "Move", 
-                x: p.logical.x, 
-                y: p.logical.y,
-                vel_x: p.logical.vel_x, 
-                vel_y: p.logical.vel_y 
-            };
-            debugLog("Sending Move:", moveMsg);
-            
-            let encodedMsg;
-            if (wasmReady) {
-                // Use WebAssembly for encoding
-                const moveValues = [p.logical.x, p.logical.y, p.logical.vel_x, p.logical.vel_y];
-                encodedMsg = wasmModule.encode_array_message("Move", new Array(...moveValues));
-            } else {
-                // Fallback to msgpack-lite
-                encodedMsg = msgpack.encode(moveMsg);
-            }
-            
-            socket.send(encodedMsg);
-        }
+        const moveMsg = {
+            x: p.logical.x,
+            y: p.logical.y,
+            vel_x: p.logical.vel_x,
+            vel_y: p.logical.vel_y
+        };
+        debugLog("Sending Move:", moveMsg);
+        sendClientMessage("Move", moveMsg);
     } else {
         console.warn("Mouse click ignored - invalid player ID or not initialized:", playerId);
     }
@@ -459,27 +775,7 @@ function updatePlayers(dt) {
                 p.logical.vel_y = 0;
                 
                 // Send update to server about stopping
-                if (socket && socket.readyState === WebSocket.OPEN) {
-                    const moveMsg = { 
-                        type: "Move", 
-                        x: p.logical.x, 
-                        y: p.logical.y,
-                        vel_x: 0, 
-                        vel_y: 0 
-                    };
-                    
-                    let encodedMsg;
-                    if (wasmReady) {
-                        // Use WebAssembly for encoding
-                        const moveValues = [p.logical.x, p.logical.y, 0, 0];
-                        encodedMsg = wasmModule.encode_array_message("Move", new Array(...moveValues));
-                    } else {
-                        // Fallback to msgpack-lite
-                        encodedMsg = msgpack.encode(moveMsg);
-                    }
-                    
-                    socket.send(encodedMsg);
-                }
+                sendClientMessage("Move", { x: p.logical.x, y: p.logical.y, vel_x: 0, vel_y: 0 });
                 
                 // Clear click target since we've reached it
                 lastClickTarget = { x: null, y: null };
@@ -537,28 +833,14 @@ function updatePlayers(dt) {
                     ball.logical.vy = dirY * KICK_POWER;
                     
                     // Send kick message to server
-                    if (socket && socket.readyState === WebSocket.OPEN) {
-                        const kickMsg = {
-                            type: "Kick",
-                            x: ball.logical.x,
-                            y: ball.logical.y,
-                            dirX: dirX,
-                            dirY: dirY
-                        };
-                        debugLog("Sending Kick:", kickMsg);
-                        
-                        let encodedMsg;
-                        if (wasmReady) {
-                            // Use WebAssembly for encoding
-                            const kickValues = [ball.logical.x, ball.logical.y, dirX, dirY];
-                            encodedMsg = wasmModule.encode_array_message("Kick", new Array(...kickValues));
-                        } else {
-                            // Fallback to msgpack-lite
-                            encodedMsg = msgpack.encode(kickMsg);
-                        }
-                        
-                        socket.send(encodedMsg);
-                    }
+                    const kickMsg = {
+                        x: ball.logical.x,
+                        y: ball.logical.y,
+                        dir_x: dirX,
+                        dir_y: dirY
+                    };
+                    debugLog("Sending Kick:", kickMsg);
+                    sendClientMessage("Kick", kickMsg);
                 }
                 
                 // Reset click state and set kick time
@@ -623,6 +905,42 @@ function draw() {
     ctx.fillText(`Players: ${Object.keys(players).length}`, 10, 20);
     ctx.fillText(`Your ID: ${playerId !== null && playerId !== undefined ? playerId : 'undefined'}`, 10, 40);
     ctx.fillText(`WASM MessagePack: ${wasmReady ? 'Enabled' : 'Disabled'}`, 10, 60);
+    const teamLabel = myTeam === null ? '' : ` (team ${myTeam === 0 ? 'left' : 'right'})`;
+    const roomLabel = queuedForMatch ? 'waiting for match... ' : (currentRoom ? currentRoom.name : '-');
+    ctx.fillText(`Room: ${roomLabel}${teamLabel}  [Q - find match, S - start match]`, 10, 120);
+    
+    // Score: слева всегда счёт команды у левых ворот
+    ctx.font = "24px Arial";
+    ctx.textAlign = "center";
+    const sideScore = leftSide === 0 ? score : [score[1], score[0]];
+    ctx.fillText(`${sideScore[0]} : ${sideScore[1]}`, canvas.width / 2, 30);
+    if (clock) {
+        const minutes = Math.floor(clock.remaining / 60);
+        const seconds = String(clock.remaining % 60).padStart(2, "0");
+        ctx.font = "14px Arial";
+        ctx.fillText(`Half ${clock.half}  ${minutes}:${seconds}`, canvas.width / 2, 48);
+        ctx.font = "24px Arial";
+    }
+    if (lastGoal && Date.now() - lastGoal.time < 2000) {
+        const scorer = lastGoal.scorerId === null ? "" : ` (player ${lastGoal.scorerId})`;
+        ctx.fillText(`GOAL for ${lastGoal.team === 0 ? "left" : "right"} team${scorer}!`, canvas.width / 2, 60);
+    }
+    if (matchResult) {
+        const winner = matchResult.winner === null ? "Draw" : `${matchResult.winner === 0 ? "Red" : "Blue"} team wins`;
+        ctx.fillText(`${winner} ${matchResult.score[0]}:${matchResult.score[1]}`, canvas.width / 2, 90);
+        ctx.font = "14px Arial";
+        matchResult.stats.forEach((player, i) => {
+            ctx.fillText(`Player ${player.id}: ${player.goals} goals, ${player.kicks} kicks, ${player.touches} touches`,
+                         canvas.width / 2, 115 + i * 18);
+        });
+    }
+    if (connectionError) {
+        ctx.fillStyle = "red";
+        ctx.fillText(connectionError, canvas.width / 2, canvas.height / 2);
+        ctx.fillStyle = "black";
+    }
+    ctx.font = "14px Arial";
+    ctx.textAlign = "left";
     
     // НОВОЕ: Отладочная информация о мяче
     if (DEBUG) {
@@ -649,9 +967,10 @@ function draw() {
             ctx.fill();
         }
         
-        // Draw player avatar
+        // Draw player avatar, colored by team when known
+        const team = playerTeams[id];
         ctx.drawImage(
-            avatarImg,
+            team === undefined ? avatarImg : teamAvatarImgs[team],
             x - AVATAR_RADIUS,
             y - AVATAR_RADIUS,
             AVATAR_RADIUS * 2,
@@ -0,0 +1,92 @@
+use std::env;
+use std::str::FromStr;
+
+use crate::match_state::MatchSettings;
+
+// Значения по умолчанию; каждое можно переопределить переменной окружения
+const DEFAULT_TICK_RATE: u32 = 30;
+const MAX_TICK_RATE: u32 = 240;
+const DEFAULT_MAX_ROOM_PLAYERS: usize = 10;
+const DEFAULT_TEAM_SIZE: usize = 2;
+const DEFAULT_MATCH_DURATION_SECS: u32 = 300;
+const DEFAULT_HALFTIME: bool = true;
+// Клиент шлёт один кадр за fixedUpdate (30 в секунду) с Move/Kick/Ack; к этому
+// запасу добавляется tick_rate на случай клиентов, подтверждающих каждый снимок
+const BASE_MAX_MESSAGES_PER_SEC: u32 = 90;
+const DEFAULT_MAX_BYTES_PER_SEC: usize = 32 * 1024;
+const DEFAULT_MAX_FRAME_SIZE: usize = 16 * 1024;
+
+/// Limits on what a single connection may send.
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimits {
+    /// Client messages per second, counting each message of a batch
+    /// (`YORK_MAX_MESSAGES_PER_SEC`, by default scaled with the tick rate).
+    pub messages_per_sec: u32,
+    /// Incoming bytes per second (`YORK_MAX_BYTES_PER_SEC`).
+    pub bytes_per_sec: usize,
+    /// Largest accepted WebSocket frame and message (`YORK_MAX_FRAME_SIZE`).
+    pub max_frame_size: usize,
+}
+
+/// Server settings read once at startup.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Game loop frequency in Hz (`YORK_TICK_RATE`).
+    pub tick_rate: u32,
+    /// Upper bound for a room's `max_players` (`YORK_MAX_ROOM_PLAYERS`).
+    pub max_room_players: usize,
+    /// Players per team in matchmade rooms, e.g. 2 for 2v2 (`YORK_TEAM_SIZE`).
+    pub team_size: usize,
+    /// Match length in seconds, both halves together (`YORK_MATCH_DURATION`).
+    pub match_duration_secs: u32,
+    /// Split matches into two halves and swap sides at halftime (`YORK_HALFTIME`).
+    pub halftime: bool,
+    pub rate_limits: RateLimits,
+}
+
+impl ServerConfig {
+    pub fn from_env() -> Self {
+        let max_room_players = env_or("YORK_MAX_ROOM_PLAYERS", DEFAULT_MAX_ROOM_PLAYERS).max(2);
+        let tick_rate = env_or("YORK_TICK_RATE", DEFAULT_TICK_RATE).clamp(1, MAX_TICK_RATE);
+
+        ServerConfig {
+            tick_rate,
+            max_room_players,
+            // Обе команды матча должны поместиться в одну комнату
+            team_size: env_or("YORK_TEAM_SIZE", DEFAULT_TEAM_SIZE).clamp(1, max_room_players / 2),
+            match_duration_secs: env_or("YORK_MATCH_DURATION", DEFAULT_MATCH_DURATION_SECS).max(1),
+            halftime: env_or("YORK_HALFTIME", DEFAULT_HALFTIME),
+            rate_limits: RateLimits {
+                messages_per_sec: env_or("YORK_MAX_MESSAGES_PER_SEC", BASE_MAX_MESSAGES_PER_SEC + tick_rate).max(1),
+                bytes_per_sec: env_or("YORK_MAX_BYTES_PER_SEC", DEFAULT_MAX_BYTES_PER_SEC).max(1),
+                // Hello и другие служебные сообщения должны проходить всегда
+                max_frame_size: env_or("YORK_MAX_FRAME_SIZE", DEFAULT_MAX_FRAME_SIZE).max(1024),
+            },
+        }
+    }
+
+    pub fn match_settings(&self) -> MatchSettings {
+        MatchSettings {
+            duration_secs: self.match_duration_secs as f64,
+            halftime: self.halftime,
+        }
+    }
+
+    /// Length of one simulation step in seconds.
+    pub fn tick_dt(&self) -> f64 {
+        1.0 / self.tick_rate as f64
+    }
+}
+
+fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    match env::var(name) {
+        Ok(raw) => match raw.parse() {
+            Ok(value) => value,
+            Err(_) => {
+                eprintln!("Ignoring invalid {}={:?}, using default", name, raw);
+                default
+            }
+        },
+        Err(_) => default,
+    }
+}
@@ -1,128 +1,91 @@
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::mpsc;
-use tokio_tungstenite::tungstenite::Message;
+use tokio::time::MissedTickBehavior;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::{CloseFrame, WebSocketConfig};
+use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+use tokio_tungstenite::WebSocketStream;
+use futures_util::stream::SplitStream;
 use futures_util::{SinkExt, StreamExt};
-use serde::{Serialize, Deserialize};
+use york_protocol::snapshot::{EntityState, PositionEncoding, Snapshot, SnapshotDelta};
+use york_protocol::{Capability, ClientId, ClientMessage, ProtocolError, ServerMessage, PROTOCOL_VERSION};
 
-// Явно импортируем rmp и rmp_serde
+// Явно импортируем rmp_serde
 extern crate rmp_serde;
-extern crate rmp;
+
+mod config;
+mod match_state;
+mod matchmaking;
+mod outbound;
+mod physics;
+mod rate_limit;
+mod rooms;
+mod snapshot;
+
+use config::{RateLimits, ServerConfig};
+use match_state::ClockEvent;
+use matchmaking::MatchQueue;
+use outbound::{Outbound, Outgoing};
+use physics::{KickOutcome, MoveOutcome};
+use rate_limit::{RateLimiter, Verdict};
+use rooms::{Room, RoomError, RoomId, RoomRegistry, Team};
 
 // Определяем тип ошибки, который можно безопасно передавать между потоками
 type BoxError = Box<dyn std::error::Error + Send + Sync>;
 
 // Game state and client management
-type ClientId = u32;
-type Clients = Arc<Mutex<HashMap<ClientId, mpsc::UnboundedSender<Message>>>>;
-
-// Message structures for MessagePack
-#[derive(Serialize, Deserialize, Debug, Clone)]
-#[serde(tag = "type")]
-enum GameMessage {
-    #[serde(rename = "Init")]
-    Init { id: ClientId },
-    
-    #[serde(rename = "Joined")]
-    Joined { id: ClientId },
-    
-    #[serde(rename = "Left")]
-    Left { id: ClientId },
-    
-    #[serde(rename = "PlayerMove")]
-    PlayerMove { 
-        id: ClientId, 
-        x: f64, 
-        y: f64, 
-        #[serde(rename = "vel_x")] 
-        vel_x: f64, 
-        #[serde(rename = "vel_y")] 
-        vel_y: f64 
-    },
-    
-    #[serde(rename = "Kick")]
-    Kick { 
-        id: ClientId, 
-        x: f64, 
-        y: f64, 
-        #[serde(rename = "dirX")] 
-        dir_x: f64, 
-        #[serde(rename = "dirY")] 
-        dir_y: f64 
-    },
-}
+type Clients = Arc<Mutex<HashMap<ClientId, ClientHandle>>>;
+type SharedRooms = Arc<Mutex<RoomRegistry>>;
+type SharedQueue = Arc<Mutex<MatchQueue>>;
 
-// Client message structures
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(tag = "type")]
-enum ClientMessage {
-    #[serde(rename = "Move")]
-    Move { 
-        x: f64, 
-        y: f64, 
-        #[serde(rename = "vel_x")] 
-        vel_x: f64, 
-        #[serde(rename = "vel_y")] 
-        vel_y: f64 
-    },
-    
-    #[serde(rename = "Kick")]
-    Kick { 
-        x: f64, 
-        y: f64, 
-        #[serde(rename = "dirX")] 
-        dir_x: f64, 
-        #[serde(rename = "dirY")] 
-        dir_y: f64 
-    },
-}
+/// How long a new connection may take to send its `Hello`.
+const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long to wait for the client to answer our close frame.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long one frame may wait for a client that has stopped reading before
+/// the client is disconnected.
+const SEND_TIMEOUT: Duration = Duration::from_secs(5);
 
-// Функции для создания сообщений в формате массива
+/// Optional features this server agrees to when a client asks for them.
+const SUPPORTED_CAPABILITIES: [Capability; 5] = [
+    Capability::DeltaSnapshots,
+    Capability::Batching,
+    Capability::Float32Positions,
+    Capability::QuantizedPositions,
+    Capability::Lz4Frames,
+];
 
-// Для Init и Joined (только ID)
-fn create_simple_message(msg_type: &str, id: ClientId) -> Result<Vec<u8>, BoxError> {
-    let mut buf = Vec::new();
-    
-    // Массив из 2 элементов: тип и ID
-    rmp::encode::write_array_len(&mut buf, 2)?;
-    rmp::encode::write_str(&mut buf, msg_type)?;
-    rmp::encode::write_u32(&mut buf, id)?;
-    
-    Ok(buf)
+/// Per-connection state shared between the connection task and the game loop.
+struct ClientHandle {
+    outbound: Arc<Outbound>,
+    /// Latest snapshot tick the client confirmed with `Ack`; `None` until the
+    /// first ack, in which case the client keeps receiving full snapshots.
+    acked_tick: Option<u32>,
+    /// Acks for ticks up to this one belong to a previous room and are ignored.
+    ack_floor: u32,
+    room_id: Option<RoomId>,
+    /// Features agreed on in the `Hello`/`Welcome` handshake.
+    capabilities: Vec<Capability>,
+    /// Form of full `Snapshot`s, from the capabilities.
+    positions: PositionEncoding,
+    /// Kicks the server refused (out of reach, on cooldown, bad direction).
+    kick_violations: u32,
 }
 
-// Для PlayerMove (id, x, y, vel_x, vel_y)
-fn create_move_message(id: ClientId, x: f64, y: f64, vel_x: f64, vel_y: f64) -> Result<Vec<u8>, BoxError> {
-    let mut buf = Vec::new();
-    
-    // Массив из 6 элементов: тип, id, x, y, vel_x, vel_y
-    rmp::encode::write_array_len(&mut buf, 6)?;
-    rmp::encode::write_str(&mut buf, "PlayerMove")?;
-    rmp::encode::write_u32(&mut buf, id)?;
-    rmp::encode::write_f64(&mut buf, x)?;
-    rmp::encode::write_f64(&mut buf, y)?;
-    rmp::encode::write_f64(&mut buf, vel_x)?;
-    rmp::encode::write_f64(&mut buf, vel_y)?;
-    
-    Ok(buf)
-}
+impl ClientHandle {
+    /// Queues a message for the socket task; `false` if the client is gone.
+    fn send(&self, message: Message) -> bool {
+        self.outbound.push(message)
+    }
 
-// Для Kick (id, x, y, dirX, dirY)
-fn create_kick_message(id: ClientId, x: f64, y: f64, dir_x: f64, dir_y: f64) -> Result<Vec<u8>, BoxError> {
-    let mut buf = Vec::new();
-    
-    // Массив из 6 элементов: тип, id, x, y, dirX, dirY
-    rmp::encode::write_array_len(&mut buf, 6)?;
-    rmp::encode::write_str(&mut buf, "Kick")?;
-    rmp::encode::write_u32(&mut buf, id)?;
-    rmp::encode::write_f64(&mut buf, x)?;
-    rmp::encode::write_f64(&mut buf, y)?;
-    rmp::encode::write_f64(&mut buf, dir_x)?;
-    rmp::encode::write_f64(&mut buf, dir_y)?;
-    
-    Ok(buf)
+    /// Queues a `Snapshot` or `Delta`, replacing one the client has not
+    /// received yet.
+    fn send_state(&self, message: Message) -> bool {
+        self.outbound.push_state(message)
+    }
 }
 
 // Вспомогательная функция для HEX-дампа бинарных данных
@@ -130,8 +93,8 @@ fn hex_dump(data: &[u8], max_bytes: usize) -> String {
     let bytes_to_show = std::cmp::min(data.len(), max_bytes);
     let mut result = String::new();
     
-    for i in 0..bytes_to_show {
-        result.push_str(&format!("{:02x} ", data[i]));
+    for (i, byte) in data.iter().enumerate().take(bytes_to_show) {
+        result.push_str(&format!("{:02x} ", byte));
         if (i + 1) % 16 == 0 && i + 1 < bytes_to_show {
             result.push('\n');
         }
@@ -151,10 +114,25 @@ async fn main() -> Result<(), BoxError> {
     let listener = TcpListener::bind(&addr).await?;
     println!("WebSocket server listening on: {}", addr);
 
+    let config = ServerConfig::from_env();
+    println!("Game loop running at {} Hz, up to {} players per room, {}v{} matchmaking",
+             config.tick_rate, config.max_room_players, config.team_size, config.team_size);
+    println!("Matches last {} s{}", config.match_duration_secs,
+             if config.halftime { " in two halves" } else { "" });
+
     // Shared game state
     let clients: Clients = Arc::new(Mutex::new(HashMap::new()));
+    let rooms: SharedRooms = Arc::new(Mutex::new(RoomRegistry::new(config.max_room_players, config.match_settings())));
+    let queue: SharedQueue = Arc::new(Mutex::new(MatchQueue::new(config.team_size)));
     let mut client_id_counter: ClientId = 0;
 
+    let rate_limits = config.rate_limits;
+    println!("Clients may send {} messages and {} bytes per second, frames up to {} bytes",
+             rate_limits.messages_per_sec, rate_limits.bytes_per_sec, rate_limits.max_frame_size);
+
+    // Физика и рассылка состояния крутятся на сервере с фиксированным шагом
+    tokio::spawn(run_game_loop(config, Arc::clone(&rooms), Arc::clone(&clients)));
+
     // Accept WebSocket connections
     while let Ok((stream, _)) = listener.accept().await {
         let peer = stream.peer_addr().unwrap();
@@ -164,10 +142,12 @@ async fn main() -> Result<(), BoxError> {
         
         // Клонируем clients для передачи в задачу
         let clients_clone = Arc::clone(&clients);
+        let rooms_clone = Arc::clone(&rooms);
+        let queue_clone = Arc::clone(&queue);
         
         // Запускаем обработку соединения в отдельной задаче
         tokio::spawn(async move {
-            if let Err(e) = handle_connection(stream, peer, clients_clone, client_id).await {
+            if let Err(e) = handle_connection(stream, peer, clients_clone, rooms_clone, queue_clone, client_id, rate_limits).await {
                 eprintln!("Error in connection handler: {}", e);
             }
         });
@@ -176,9 +156,182 @@ async fn main() -> Result<(), BoxError> {
     Ok(())
 }
 
+/// Steps every room's world at `config.tick_rate` and sends each member one
+/// message per tick with the latest state of its room: a `Delta` against the
+/// client's last acknowledged snapshot when that snapshot is still in the
+/// room's history, a full `Snapshot` otherwise.
+async fn run_game_loop(config: ServerConfig, rooms: SharedRooms, clients: Clients) {
+    let dt = config.tick_dt();
+    let mut interval = tokio::time::interval(Duration::from_secs_f64(dt));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    loop {
+        interval.tick().await;
+
+        let mut rooms_lock = rooms.lock().unwrap();
+        // 0 в Delta означает "нет базового снимка", поэтому при переполнении его пропускаем
+        rooms_lock.tick = rooms_lock.tick.wrapping_add(1).max(1);
+        let tick = rooms_lock.tick;
+
+        let clients_lock = clients.lock().unwrap();
+        for room in rooms_lock.rooms_mut() {
+            for id in room.world.step(dt) {
+                if let Some(team) = room.teams.get(&id).copied() {
+                    room.match_state.record_touch(id, team);
+                }
+            }
+            if let Some(team) = room.match_state.check_goal(&room.world.ball) {
+                handle_goal(&clients_lock, room, team);
+            }
+            if let Some(event) = room.match_state.advance_clock(dt) {
+                handle_clock_event(&clients_lock, room, event);
+            }
+            let snapshot = snapshot::capture(tick, &room.world);
+            send_room_snapshot(&clients_lock, room, &snapshot);
+            room.history.push(snapshot);
+        }
+
+        // Клиенты вне комнат тоже получают ответы (RoomList и т.п.) раз в тик
+        for client in clients_lock.values() {
+            client.outbound.flush();
+        }
+    }
+}
+
+/// Scores the goal, puts everyone back to kickoff positions and tells the room.
+fn handle_goal(clients: &HashMap<ClientId, ClientHandle>, room: &mut Room, team: Team) {
+    let scorer_id = room.world.last_touch;
+    let scorer = scorer_id.and_then(|id| room.teams.get(&id).map(|scorer_team| (id, *scorer_team)));
+    room.match_state.record_goal(team, scorer);
+    let positions = room.match_state.kickoff_positions(&room.teams);
+    room.world.reset_for_kickoff(&positions);
+
+    let score = room.match_state.score;
+    println!("Goal for team {:?} in room {} by {:?}, score {}:{}", team, room.id, scorer_id, score[0], score[1]);
+
+    let messages = [
+        ServerMessage::GoalScored { team, scorer: scorer_id, score },
+        ServerMessage::Kickoff { positions },
+        room.match_state_message(),
+    ];
+    for msg in messages.iter().filter_map(pack) {
+        send_to_members(clients, &room.members, &msg);
+    }
+}
+
+/// Sends the clock to the room every second; at halftime the teams swap sides
+/// and restart from kickoff, at full time the results are sent and the room
+/// goes back to the lobby.
+fn handle_clock_event(clients: &HashMap<ClientId, ClientHandle>, room: &mut Room, event: ClockEvent) {
+    let state = &room.match_state;
+    let messages = match event {
+        ClockEvent::Second => vec![clock_message(room)],
+        ClockEvent::HalfTime => {
+            println!("Halftime in room {}, score {}:{}", room.id, state.score[0], state.score[1]);
+            let positions = state.kickoff_positions(&room.teams);
+            room.world.reset_for_kickoff(&positions);
+            vec![
+                ServerMessage::Kickoff { positions },
+                room.match_state_message(),
+                clock_message(room),
+            ]
+        }
+        ClockEvent::FullTime => {
+            println!("Match over in room {}, score {}:{}", room.id, state.score[0], state.score[1]);
+            let positions = state.kickoff_positions(&room.teams);
+            room.world.reset_for_kickoff(&positions);
+            vec![
+                room.match_ended_message(),
+                ServerMessage::Kickoff { positions },
+                room.match_state_message(),
+            ]
+        }
+    };
+
+    for msg in messages.iter().filter_map(pack) {
+        send_to_members(clients, &room.members, &msg);
+    }
+}
+
+fn clock_message(room: &Room) -> ServerMessage {
+    ServerMessage::Clock {
+        half: room.match_state.half,
+        remaining_secs: room.match_state.remaining_secs(),
+    }
+}
+
+/// Encodes a message for the socket. Encoding into memory only fails on a
+/// bug, so the error is just logged and the message dropped.
+fn pack(msg: &ServerMessage) -> Option<Message> {
+    pack_with(msg, PositionEncoding::Float64)
+}
+
+fn pack_with(msg: &ServerMessage, positions: PositionEncoding) -> Option<Message> {
+    match msg.encode_with(positions) {
+        Ok(packed_msg) => Some(Message::Binary(packed_msg)),
+        Err(e) => {
+            eprintln!("Failed to encode {} message: {}", msg.msg_type(), e);
+            None
+        }
+    }
+}
+
+/// Wraps a large frame into the LZ4 envelope when that makes it smaller.
+fn compress_frame(data: Vec<u8>) -> Vec<u8> {
+    match york_protocol::compress_frame(&data) {
+        Ok(Some(compressed)) => compressed,
+        Ok(None) => data,
+        Err(e) => {
+            eprintln!("Failed to compress frame: {}", e);
+            data
+        }
+    }
+}
+
+fn send_to_members<'a>(clients: &HashMap<ClientId, ClientHandle>, members: impl IntoIterator<Item = &'a ClientId>, message: &Message) {
+    for client in members.into_iter().filter_map(|id| clients.get(id)) {
+        client.send(message.clone());
+    }
+}
+
+fn send_room_snapshot(clients: &HashMap<ClientId, ClientHandle>, room: &Room, snapshot: &Snapshot) {
+    // Клиенты обычно совпадают по кодировке и подтверждённым тикам, поэтому
+    // полные снимки кэшируются по кодировке, а дельты по базовому тику
+    let mut full_msgs: HashMap<PositionEncoding, Option<Message>> = HashMap::new();
+    let mut deltas: HashMap<u32, Option<Message>> = HashMap::new();
+    for client in room.members.iter().filter_map(|id| clients.get(id)) {
+        let baseline = room.history.baseline(client.acked_tick);
+        let delta = baseline.and_then(|baseline| {
+            deltas
+                .entry(baseline.tick)
+                .or_insert_with(|| pack(&ServerMessage::Delta(SnapshotDelta::between(baseline, snapshot))))
+                .clone()
+        });
+        let msg = delta.or_else(|| {
+            full_msgs
+                .entry(client.positions)
+                .or_insert_with(|| pack_with(&ServerMessage::Snapshot(snapshot.clone()), client.positions))
+                .clone()
+        });
+        if let Some(msg) = msg {
+            client.send_state(msg);
+        }
+    }
+}
+
 // Обработчик соединения теперь возвращает Result<(), BoxError>
-async fn handle_connection(stream: TcpStream, peer: SocketAddr, clients: Clients, client_id: ClientId) -> Result<(), BoxError> {
-    let ws_stream = match tokio_tungstenite::accept_async(stream).await {
+async fn handle_connection(stream: TcpStream, peer: SocketAddr, clients: Clients, rooms: SharedRooms, queue: SharedQueue, client_id: ClientId, limits: RateLimits) -> Result<(), BoxError> {
+    // Слишком большие кадры tungstenite отбрасывает сам, ещё до буферизации.
+    // permessage-deflate не согласуется: tungstenite 0.20 не поддерживает
+    // расширения и рвёт соединение на любом кадре с RSV1, а браузер после
+    // согласования сжимает свои сообщения. Большие кадры сжимает LZ4-конверт
+    // (Capability::Lz4Frames); deflate ждёт смены WebSocket-стека.
+    let ws_config = WebSocketConfig {
+        max_message_size: Some(limits.max_frame_size),
+        max_frame_size: Some(limits.max_frame_size),
+        ..WebSocketConfig::default()
+    };
+    let ws_stream = match tokio_tungstenite::accept_async_with_config(stream, Some(ws_config)).await {
         Ok(ws_stream) => ws_stream,
         Err(e) => {
             eprintln!("Error during WebSocket handshake: {}", e);
@@ -188,95 +341,271 @@ async fn handle_connection(stream: TcpStream, peer: SocketAddr, clients: Clients
 
     println!("New WebSocket connection: {} (ID: {})", peer, client_id);
 
+    let mut ws_stream = ws_stream;
+    let Some(capabilities) = handshake(&mut ws_stream, client_id).await? else {
+        println!("WebSocket connection rejected: {} (ID: {})", peer, client_id);
+        return Ok(());
+    };
+    let batching = capabilities.contains(&Capability::Batching);
+    let lz4 = capabilities.contains(&Capability::Lz4Frames);
+
     // Split the WebSocket stream
     let (mut ws_sender, mut ws_receiver) = ws_stream.split();
     
-    // Create a channel for sending messages to this client
-    let (client_sender, mut client_receiver) = mpsc::unbounded_channel();
+    // Bounded queue of messages for this client
+    let outbound = Arc::new(Outbound::new());
     
-    // Store the new client's sender
+    // Store the new client's queue
     {
         let mut clients_lock = clients.lock().unwrap();
-        clients_lock.insert(client_id, client_sender);
-    }
-
-    // Send initialization message to the new client using MessagePack as array
-    let packed_msg = create_simple_message("Init", client_id)?;
-    println!("Init message for client {}, raw bytes: {}", client_id, hex_dump(&packed_msg, 32));
-    
-    if let Err(e) = ws_sender.send(Message::Binary(packed_msg)).await {
-        eprintln!("Error sending init message: {}", e);
-        return Err(Box::new(e));
+        clients_lock.insert(client_id, ClientHandle {
+            outbound: Arc::clone(&outbound),
+            acked_tick: None,
+            ack_floor: 0,
+            room_id: None,
+            positions: PositionEncoding::negotiate(&capabilities),
+            capabilities,
+            kick_violations: 0,
+        });
     }
-    println!("Sent init message to client {}", client_id);
-    
-    // Notify all clients about the new player - using array format
-    let packed_msg = create_simple_message("Joined", client_id)?;
-    println!("Join message for client {}, raw bytes: {}", client_id, hex_dump(&packed_msg, 32));
+    // Joined рассылается участникам комнаты, когда клиент в неё войдёт
     
-    broadcast_message(&clients, client_id, Message::Binary(packed_msg)).await;
-    println!("Broadcast join message for client {}", client_id);
-    
-    // Spawn a task to forward messages from the client_receiver to the WebSocket
+    // Spawn a task to forward messages from the outbound queue to the WebSocket
     let client_id_clone = client_id;
+    let client_outbound = Arc::clone(&outbound);
     let forward_task = tokio::spawn(async move {
-        while let Some(msg) = client_receiver.recv().await {
-            if let Err(e) = ws_sender.send(msg).await {
-                eprintln!("Error sending message to client {}: {}", client_id_clone, e);
-                break;
+        // Закодированные сообщения, ждущие следующего Flush (только при batching)
+        let mut batch = Vec::new();
+        'forward: loop {
+            let frames = match client_outbound.pop().await {
+                Some(Outgoing::Message(Message::Binary(data))) if batching => {
+                    batch.extend_from_slice(&data);
+                    continue;
+                }
+                Some(Outgoing::Message(msg)) if batch.is_empty() => vec![msg],
+                // Close frame не должен обгонять накопленные сообщения
+                Some(Outgoing::Message(msg)) => vec![Message::Binary(std::mem::take(&mut batch)), msg],
+                Some(Outgoing::Flush) if !batch.is_empty() => vec![Message::Binary(std::mem::take(&mut batch))],
+                Some(Outgoing::Flush) => continue,
+                // Очередь закрыта и пуста: остаток пакета уходит перед выходом
+                None if !batch.is_empty() => vec![Message::Binary(std::mem::take(&mut batch))],
+                None => break,
+            };
+            for msg in frames {
+                let msg = match msg {
+                    Message::Binary(data) if lz4 => Message::Binary(compress_frame(data)),
+                    msg => msg,
+                };
+                // Пока send стоит, очередь не разбирается и может не переполниться
+                // (снимки заменяют друг друга), поэтому зависшего клиента отключаем здесь
+                match tokio::time::timeout(SEND_TIMEOUT, ws_sender.send(msg)).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => {
+                        eprintln!("Error sending message to client {}: {}", client_id_clone, e);
+                        client_outbound.abort();
+                        break 'forward;
+                    }
+                    Err(_) => {
+                        println!("Disconnecting client {}: send stalled for {:?}", client_id_clone, SEND_TIMEOUT);
+                        client_outbound.abort();
+                        break 'forward;
+                    }
+                }
             }
         }
     });
 
+    let mut limiter = RateLimiter::new(&limits);
+
     // Process incoming WebSocket messages
-    while let Some(result) = ws_receiver.next().await {
+    while let Some(result) = next_frame(&mut ws_receiver, &outbound, client_id).await {
         match result {
             Ok(msg) => {
-                if let Message::Binary(data) = msg {
+                // Клиент может прислать несколько сообщений в одном кадре
+                let messages = match &msg {
+                    Message::Binary(data) => Some(york_protocol::split_batch(data)),
+                    _ => None,
+                };
+                // Лимит тратит любой кадр, в том числе Text, Ping и Pong; битый, пустой
+                // или не бинарный кадр считается одним сообщением, чтобы и ими нельзя
+                // было заваливать сервер
+                let count = match &messages {
+                    Some(Ok(messages)) => messages.len().max(1),
+                    _ => 1,
+                };
+                match limiter.check(msg.len(), count) {
+                    Verdict::Allow => {}
+                    Verdict::Drop => continue,
+                    Verdict::Warn => {
+                        println!("Client {} exceeds the rate limit, dropping its messages", client_id);
+                        if let Some(msg) = pack(&ServerMessage::Warning { reason: "rate limit exceeded".to_string() }) {
+                            send_to_client(&clients, client_id, msg);
+                        }
+                        continue;
+                    }
+                    Verdict::Disconnect => {
+                        println!("Disconnecting client {}: rate limit exceeded", client_id);
+                        send_close(&clients, client_id, CloseCode::Policy, "rate limit exceeded");
+                        break;
+                    }
+                }
+
+                if let (Message::Binary(data), Some(messages)) = (&msg, messages) {
                     // Добавляем отладочную информацию
                     println!("Received binary message from client {}, size: {} bytes", client_id, data.len());
-                    println!("Message raw bytes: {}", hex_dump(&data, 32));
+                    println!("Message raw bytes: {}", hex_dump(data, 32));
                     
-                    match rmp_serde::from_slice::<ClientMessage>(&data) {
-                        Ok(client_msg) => {
-                            match client_msg {
-                                ClientMessage::Move { x, y, vel_x, vel_y } => {
-                                    println!("Client {} sent Move: x={}, y={}, vel_x={}, vel_y={}", 
-                                             client_id, x, y, vel_x, vel_y);
-                                    
-                                    // Отправляем PlayerMove в формате массива
-                                    if let Ok(packed_msg) = create_move_message(client_id, x, y, vel_x, vel_y) {
-                                        println!("PlayerMove message, raw bytes: {}", hex_dump(&packed_msg, 32));
-                                        broadcast_message(&clients, client_id, Message::Binary(packed_msg)).await;
-                                    }
-                                },
-                                ClientMessage::Kick { x, y, dir_x, dir_y } => {
-                                    println!("Client {} sent Kick: x={}, y={}, dirX={}, dirY={}", 
-                                             client_id, x, y, dir_x, dir_y);
-                                    
-                                    // Отправляем Kick в формате массива
-                                    if let Ok(packed_msg) = create_kick_message(client_id, x, y, dir_x, dir_y) {
-                                        println!("Kick message, raw bytes: {}", hex_dump(&packed_msg, 32));
-                                        broadcast_message(&clients, client_id, Message::Binary(packed_msg)).await;
+                    let messages = match messages {
+                        Ok(messages) => messages,
+                        Err(e) => {
+                            eprintln!("Malformed frame from client {}: {}", client_id, e);
+                            eprintln!("Raw message bytes: {}", hex_dump(data, data.len()));
+                            continue;
+                        }
+                    };
+                    for message in messages {
+                        match decode_client_message(message) {
+                            Ok(client_msg) => {
+                                match client_msg {
+                                    ClientMessage::Move { x, y, vel_x, vel_y } => {
+                                        println!("Client {} sent Move: x={}, y={}, vel_x={}, vel_y={}", 
+                                                 client_id, x, y, vel_x, vel_y);
+                                        
+                                        // Остальные игроки увидят движение в следующем Snapshot
+                                        if let Some(room_id) = client_room(&clients, client_id) {
+                                            let correction = rooms.lock().unwrap().get_mut(room_id).and_then(|room| {
+                                                match room.world.apply_move(client_id, x, y, vel_x, vel_y) {
+                                                    MoveOutcome::Accepted => None,
+                                                    outcome => {
+                                                        println!("{:?} Move from client {}", outcome, client_id);
+                                                        room.world.players.get(&client_id).map(|player| EntityState {
+                                                            x: player.x,
+                                                            y: player.y,
+                                                            vel_x: player.vel_x,
+                                                            vel_y: player.vel_y,
+                                                        })
+                                                    }
+                                                }
+                                            });
+                                            if let Some(msg) = correction.and_then(|state| pack(&ServerMessage::Correction(state))) {
+                                                send_to_client(&clients, client_id, msg);
+                                            }
+                                        }
+                                    },
+                                    ClientMessage::Kick { x, y, dir_x, dir_y } => {
+                                        println!("Client {} sent Kick: x={}, y={}, dirX={}, dirY={}", 
+                                                 client_id, x, y, dir_x, dir_y);
+                                        
+                                        // Удар применяется к серверному мячу; координаты мяча от клиента не используются
+                                        // Отклонённый удар клиент уже показал у себя - его поправит следующий Snapshot
+                                        let outcome = client_room(&clients, client_id)
+                                            .and_then(|room_id| rooms.lock().unwrap().get_mut(room_id)
+                                                .map(|room| {
+                                                    let outcome = room.world.try_kick(client_id, dir_x, dir_y);
+                                                    if let (KickOutcome::Accepted, Some(team)) = (outcome, room.teams.get(&client_id).copied()) {
+                                                        room.match_state.record_kick(client_id, team);
+                                                    }
+                                                    outcome
+                                                }));
+                                        if let Some(outcome) = outcome.filter(|outcome| *outcome != KickOutcome::Accepted) {
+                                            let violations = clients.lock().unwrap().get_mut(&client_id).map_or(0, |client| {
+                                                client.kick_violations += 1;
+                                                client.kick_violations
+                                            });
+                                            println!("Rejected Kick from client {}: {:?} ({} violations)", client_id, outcome, violations);
+                                        }
+                                    },
+                                    ClientMessage::Hello { .. } => {
+                                        println!("Ignoring repeated Hello from client {}", client_id);
+                                    },
+                                    ClientMessage::Ack { tick } => {
+                                        // Приходит каждый тик, поэтому без логирования
+                                        if let Some(client) = clients.lock().unwrap().get_mut(&client_id) {
+                                            // Без согласованных дельт клиент всегда получает полные снимки
+                                            let deltas = client.capabilities.contains(&Capability::DeltaSnapshots);
+                                            if deltas && tick > client.ack_floor {
+                                                client.acked_tick = Some(tick);
+                                            }
+                                        }
+                                    },
+                                    ClientMessage::CreateRoom { name, max_players } => {
+                                        println!("Client {} creates room {:?} for {} players", client_id, name, max_players);
+                                        
+                                        queue.lock().unwrap().remove(client_id);
+                                        let created = rooms.lock().unwrap().create(&name, max_players as usize);
+                                        let result = created.and_then(|room_id| enter_room(&clients, &rooms, client_id, room_id));
+                                        if let Err(e) = result {
+                                            send_room_error(&clients, client_id, &e);
+                                        }
+                                    },
+                                    ClientMessage::JoinRoom { room_id } => {
+                                        println!("Client {} joins room {}", client_id, room_id);
+                                        
+                                        queue.lock().unwrap().remove(client_id);
+                                        if let Err(e) = enter_room(&clients, &rooms, client_id, room_id) {
+                                            send_room_error(&clients, client_id, &e);
+                                        }
+                                    },
+                                    ClientMessage::LeaveRoom {} => {
+                                        if let Some(room_id) = exit_room(&clients, &rooms, client_id) {
+                                            println!("Client {} left room {}", client_id, room_id);
+                                            if let Some(msg) = pack(&ServerMessage::RoomLeft { room_id }) {
+                                                send_to_client(&clients, client_id, msg);
+                                            }
+                                        }
+                                    },
+                                    ClientMessage::ListRooms {} => {
+                                        let list = rooms.lock().unwrap().list();
+                                        if let Some(msg) = pack(&ServerMessage::RoomList { rooms: list }) {
+                                            send_to_client(&clients, client_id, msg);
+                                        }
+                                    },
+                                    ClientMessage::QueueForMatch {} => {
+                                        // В очереди игрок не может оставаться в комнате
+                                        if let Some(room_id) = exit_room(&clients, &rooms, client_id) {
+                                            if let Some(msg) = pack(&ServerMessage::RoomLeft { room_id }) {
+                                                send_to_client(&clients, client_id, msg);
+                                            }
+                                        }
+                                        
+                                        if queue.lock().unwrap().enqueue(client_id) {
+                                            println!("Client {} queued for a match", client_id);
+                                            form_matches(&clients, &rooms, &queue);
+                                        }
+                                    },
+                                    ClientMessage::StartMatch {} => {
+                                        let result = client_room(&clients, client_id)
+                                            .ok_or(RoomError::NotInRoom)
+                                            .and_then(|room_id| start_match(&clients, &rooms, room_id));
+                                        match result {
+                                            Ok(()) => println!("Client {} started a match", client_id),
+                                            Err(e) => send_room_error(&clients, client_id, &e),
+                                        }
                                     }
                                 }
-                            }
-                        },
-                        Err(e) => {
-                            eprintln!("Failed to deserialize MessagePack data: {}", e);
-                            // Отладка: печатаем первые байты сообщения для анализа
-                            if !data.is_empty() {
-                                eprintln!("Raw message bytes: {}", hex_dump(&data, data.len()));
-                            }
-                            
-                            // Пробуем ручную десериализацию для отладки
-                            if let Ok(raw_value) = rmp_serde::from_slice::<serde_json::Value>(&data) {
-                                eprintln!("Raw deserialized as JSON: {:?}", raw_value);
+                            },
+                            Err(e) => {
+                                eprintln!("Failed to decode message from client {}: {}", client_id, e);
+                                // Отладка: печатаем первые байты сообщения для анализа
+                                if !message.is_empty() {
+                                    eprintln!("Raw message bytes: {}", hex_dump(message, message.len()));
+                                }
+                                
+                                // Пробуем ручную десериализацию для отладки
+                                if let Ok(raw_value) = rmp_serde::from_slice::<serde_json::Value>(message) {
+                                    eprintln!("Raw deserialized as JSON: {:?}", raw_value);
+                                }
                             }
                         }
                     }
                 }
             },
+            Err(WsError::Capacity(e)) => {
+                // Close frame - по возможности: из-за непрочитанного остатка кадра ОС может сбросить соединение
+                println!("Disconnecting client {}: {}", client_id, e);
+                send_close(&clients, client_id, CloseCode::Size, "frame too large");
+                break;
+            }
             Err(e) => {
                 eprintln!("WebSocket error for client {}: {}", client_id, e);
                 break;
@@ -284,38 +613,292 @@ async fn handle_connection(stream: TcpStream, peer: SocketAddr, clients: Clients
         }
     }
 
-    // Client disconnected
+    // Client disconnected: drop it from the queue, notify its room, then forget it
+    queue.lock().unwrap().remove(client_id);
+    exit_room(&clients, &rooms, client_id);
     {
         let mut clients_lock = clients.lock().unwrap();
         clients_lock.remove(&client_id);
     }
+    outbound.close();
 
-    // Notify other clients about the disconnection - using array format
-    if let Ok(packed_msg) = create_simple_message("Left", client_id) {
-        broadcast_message(&clients, client_id, Message::Binary(packed_msg)).await;
+    // Задача отправки допишет очередь (включая close frame) и завершится,
+    // но ждём её недолго: медленный клиент может не читать вовсе
+    let abort_handle = forward_task.abort_handle();
+    if tokio::time::timeout(CLOSE_TIMEOUT, forward_task).await.is_err() {
+        abort_handle.abort();
     }
-
-    // Cancel the send task
-    forward_task.abort();
     
     println!("WebSocket connection closed: {} (ID: {})", peer, client_id);
     
     Ok(())
 }
 
-async fn broadcast_message(clients: &Clients, exclude_id: ClientId, message: Message) {
+/// Next frame from the client, or `None` once its outbound queue has been
+/// over the limit for too long or the socket task has given up on it, and it
+/// should be disconnected.
+async fn next_frame(ws_receiver: &mut SplitStream<WebSocketStream<TcpStream>>, outbound: &Outbound, client_id: ClientId) -> Option<Result<Message, WsError>> {
+    tokio::select! {
+        frame = ws_receiver.next() => frame,
+        _ = outbound.overflowed() => {
+            println!("Disconnecting client {}: too slow, outbound queue overflow", client_id);
+            None
+        }
+        // Причину уже записала задача отправки
+        _ = outbound.aborted() => None,
+    }
+}
+
+/// Accepts both encodings of a client message: the positional array written by
+/// `ClientMessage::encode` and the older `{"type": ...}` map.
+fn decode_client_message(data: &[u8]) -> Result<ClientMessage, ProtocolError> {
+    match data.first() {
+        // fixarray, array 16, array 32
+        Some(0x90..=0x9f | 0xdc | 0xdd) => ClientMessage::decode(data),
+        _ => rmp_serde::from_slice::<ClientMessage>(data).map_err(|e| ProtocolError::Decode(e.to_string())),
+    }
+}
+
+/// Waits for the client's `Hello` and answers with `Welcome` and the agreed
+/// capabilities. A client that sends anything else, sends nothing in time or
+/// speaks another protocol version is sent a close frame (after
+/// `VersionMismatch` in the latter case) and `None` is returned.
+async fn handshake(ws_stream: &mut WebSocketStream<TcpStream>, client_id: ClientId) -> Result<Option<Vec<Capability>>, BoxError> {
+    // Один срок на весь обмен: Ping/Pong не продлевают ожидание Hello
+    let deadline = tokio::time::Instant::now() + HELLO_TIMEOUT;
+    let hello = loop {
+        match tokio::time::timeout_at(deadline, ws_stream.next()).await {
+            Err(_) => {
+                close_connection(ws_stream, CloseCode::Policy, "Hello expected").await;
+                return Ok(None);
+            }
+            Ok(None) => return Ok(None),
+            Ok(Some(Err(e))) => return Err(Box::new(e)),
+            Ok(Some(Ok(Message::Binary(data)))) => break decode_client_message(&data),
+            // Ping/Pong обрабатывает tungstenite
+            Ok(Some(Ok(Message::Ping(_) | Message::Pong(_)))) => continue,
+            Ok(Some(Ok(_))) => {
+                close_connection(ws_stream, CloseCode::Unsupported, "binary MessagePack expected").await;
+                return Ok(None);
+            }
+        }
+    };
+
+    let (protocol_version, client_build, offered) = match hello {
+        Ok(ClientMessage::Hello { protocol_version, client_build, capabilities }) => (protocol_version, client_build, capabilities),
+        other => {
+            eprintln!("Client {} did not start with Hello: {:?}", client_id, other);
+            close_connection(ws_stream, CloseCode::Policy, "Hello expected").await;
+            return Ok(None);
+        }
+    };
+
+    if protocol_version != PROTOCOL_VERSION {
+        println!("Client {} ({}) speaks protocol {}, server speaks {}", client_id, client_build, protocol_version, PROTOCOL_VERSION);
+        let mismatch = ServerMessage::VersionMismatch { server_version: PROTOCOL_VERSION, client_version: protocol_version };
+        ws_stream.send(Message::Binary(mismatch.encode()?)).await?;
+        close_connection(ws_stream, CloseCode::Policy, "protocol version mismatch").await;
+        return Ok(None);
+    }
+
+    // Каждая возможность один раз, в порядке SUPPORTED_CAPABILITIES,
+    // сколько бы раз и в каком порядке клиент её ни прислал
+    let offered: Vec<Capability> = offered.iter().filter_map(|name| Capability::from_name(name)).collect();
+    let capabilities: Vec<Capability> = SUPPORTED_CAPABILITIES
+        .into_iter()
+        .filter(|capability| offered.contains(capability))
+        .collect();
+
+    let welcome = ServerMessage::Welcome {
+        protocol_version: PROTOCOL_VERSION,
+        client_id,
+        capabilities: capabilities.iter().map(|capability| capability.name().to_string()).collect(),
+    };
+    let packed_msg = welcome.encode()?;
+    println!("Welcome message for client {} ({}), raw bytes: {}", client_id, client_build, hex_dump(&packed_msg, 32));
+    ws_stream.send(Message::Binary(packed_msg)).await?;
+    println!("Client {} agreed on capabilities {:?}", client_id, capabilities);
+
+    Ok(Some(capabilities))
+}
+
+/// Sends a close frame and gives the client a moment to answer it.
+async fn close_connection(ws_stream: &mut WebSocketStream<TcpStream>, code: CloseCode, reason: &'static str) {
+    let frame = CloseFrame { code, reason: reason.into() };
+    if let Err(e) = ws_stream.close(Some(frame)).await {
+        eprintln!("Error closing connection: {}", e);
+        return;
+    }
+    let _ = tokio::time::timeout(CLOSE_TIMEOUT, async {
+        while let Some(Ok(_)) = ws_stream.next().await {}
+    }).await;
+}
+
+fn client_room(clients: &Clients, client_id: ClientId) -> Option<RoomId> {
+    clients.lock().unwrap().get(&client_id).and_then(|client| client.room_id)
+}
+
+/// Moves the client into `room_id`, leaving its current room only once the
+/// new one has accepted it. Sends `RoomJoined` to the client and `Joined` to
+/// the other members.
+fn enter_room(clients: &Clients, rooms: &SharedRooms, client_id: ClientId, room_id: RoomId) -> Result<(), RoomError> {
+    let mut rooms_lock = rooms.lock().unwrap();
+    let current = client_room(clients, client_id);
+    if current == Some(room_id) {
+        return Err(RoomError::AlreadyInRoom);
+    }
+
+    let room = rooms_lock.join(room_id, client_id)?;
+    let joined_msg = pack(&room.joined_message());
+    let match_state_msg = pack(&room.match_state_message());
+    let members: Vec<ClientId> = room.members.iter().copied().collect();
+
+    if let Some(old_room_id) = current {
+        let remaining = rooms_lock.leave(old_room_id, client_id);
+        if let Some(msg) = pack(&ServerMessage::Left { id: client_id }) {
+            broadcast_to_room(clients, &remaining, client_id, msg);
+        }
+    }
+
+    {
+        let mut clients_lock = clients.lock().unwrap();
+        if let Some(client) = clients_lock.get_mut(&client_id) {
+            client.room_id = Some(room_id);
+            // Снимки старой комнаты больше не годятся как базовые
+            client.acked_tick = None;
+            client.ack_floor = rooms_lock.tick;
+        }
+    }
+
+    if let Some(msg) = joined_msg {
+        send_to_client(clients, client_id, msg);
+    }
+    if let Some(msg) = pack(&ServerMessage::Joined { id: client_id }) {
+        broadcast_to_room(clients, &members, client_id, msg);
+    }
+    // Состав команд изменился - все участники получают новый MatchState
+    if let Some(msg) = match_state_msg {
+        send_to_members(&clients.lock().unwrap(), &members, &msg);
+    }
+
+    Ok(())
+}
+
+/// Removes the client from its room, if any, and tells the remaining members.
+/// Returns the room the client was in.
+fn exit_room(clients: &Clients, rooms: &SharedRooms, client_id: ClientId) -> Option<RoomId> {
+    let mut rooms_lock = rooms.lock().unwrap();
+    let room_id = {
+        let mut clients_lock = clients.lock().unwrap();
+        let client = clients_lock.get_mut(&client_id)?;
+        client.acked_tick = None;
+        client.ack_floor = rooms_lock.tick;
+        client.room_id.take()?
+    };
+
+    let remaining = rooms_lock.leave(room_id, client_id);
+    if let Some(msg) = pack(&ServerMessage::Left { id: client_id }) {
+        broadcast_to_room(clients, &remaining, client_id, msg);
+    }
+    if let Some(room) = rooms_lock.get_mut(room_id) {
+        if let Some(msg) = pack(&room.match_state_message()) {
+            send_to_members(&clients.lock().unwrap(), &room.members, &msg);
+        }
+    }
+
+    Some(room_id)
+}
+
+/// Creates a room for every full match waiting in the queue, moves its players
+/// in and tells each of them their team with `MatchFound`.
+fn form_matches(clients: &Clients, rooms: &SharedRooms, queue: &SharedQueue) {
+    let mut queue_lock = queue.lock().unwrap();
+    let match_size = queue_lock.team_size() * 2;
+
+    while let Some((name, players)) = queue_lock.pop_match() {
+        // Имя могло быть занято комнатой, созданной вручную
+        let created = (1..)
+            .map(|attempt| if attempt == 1 { name.clone() } else { format!("{}-{}", name, attempt) })
+            .map(|candidate| rooms.lock().unwrap().create(&candidate, match_size))
+            .find(|result| result != &Err(RoomError::NameTaken));
+        let room_id = match created {
+            Some(Ok(room_id)) => room_id,
+            other => {
+                eprintln!("Failed to create room for {}: {:?}", name, other);
+                continue;
+            }
+        };
+
+        println!("Match formed in room {} ({}) with {} players", room_id, name, players.len());
+        for (client_id, team) in players {
+            if let Err(e) = enter_room(clients, rooms, client_id, room_id) {
+                eprintln!("Client {} could not enter match room {}: {}", client_id, room_id, e);
+                continue;
+            }
+            if let Some(room) = rooms.lock().unwrap().get_mut(room_id) {
+                room.teams.insert(client_id, team);
+            }
+            if let Some(msg) = pack(&ServerMessage::MatchFound { room_id, team }) {
+                send_to_client(clients, client_id, msg);
+            }
+        }
+
+        // Команды назначены - матч начинается сразу
+        if let Err(e) = start_match(clients, rooms, room_id) {
+            eprintln!("Could not start match in room {}: {}", room_id, e);
+        }
+    }
+}
+
+/// Starts the clock in the room, puts everyone at kickoff positions and tells the members.
+fn start_match(clients: &Clients, rooms: &SharedRooms, room_id: RoomId) -> Result<(), RoomError> {
+    let mut rooms_lock = rooms.lock().unwrap();
+    let room = rooms_lock.get_mut(room_id).ok_or(RoomError::NotFound)?;
+    let positions = room.start_match()?;
+
+    let messages = [
+        ServerMessage::Kickoff { positions },
+        room.match_state_message(),
+        clock_message(room),
+    ];
+    let clients_lock = clients.lock().unwrap();
+    for msg in messages.iter().filter_map(pack) {
+        send_to_members(&clients_lock, &room.members, &msg);
+    }
+
+    Ok(())
+}
+
+fn send_room_error(clients: &Clients, client_id: ClientId, error: &RoomError) {
+    println!("Room request from client {} failed: {}", client_id, error);
+    if let Some(msg) = pack(&ServerMessage::RoomError { reason: error.to_string() }) {
+        send_to_client(clients, client_id, msg);
+    }
+}
+
+/// Queues a close frame after the client's pending messages.
+fn send_close(clients: &Clients, client_id: ClientId, code: CloseCode, reason: &'static str) {
+    let frame = CloseFrame { code, reason: reason.into() };
+    send_to_client(clients, client_id, Message::Close(Some(frame)));
+}
+
+fn send_to_client(clients: &Clients, client_id: ClientId, message: Message) {
+    if let Some(client) = clients.lock().unwrap().get(&client_id) {
+        client.send(message);
+    }
+}
+
+fn broadcast_to_room(clients: &Clients, members: &[ClientId], exclude_id: ClientId, message: Message) {
     let clients_lock = clients.lock().unwrap();
-    let client_count = clients_lock.len();
     
     let mut sent_count = 0;
-    for (id, sender) in clients_lock.iter() {
-        if *id != exclude_id {
-            if sender.send(message.clone()).is_ok() {
+    for id in members.iter().filter(|id| **id != exclude_id) {
+        if let Some(client) = clients_lock.get(id) {
+            if client.send(message.clone()) {
                 sent_count += 1;
             }
         }
     }
     
-    println!("Broadcast message sent to {}/{} clients (excluding {})", 
-             sent_count, client_count - 1, exclude_id);
-}
\ No newline at end of file
+    println!("Room broadcast sent to {} clients (excluding {})", sent_count, exclude_id);
+}
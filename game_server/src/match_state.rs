@@ -0,0 +1,318 @@
+use std::collections::HashMap;
+
+use york_protocol::snapshot::{FIELD_HEIGHT, FIELD_WIDTH};
+use york_protocol::{MatchPhase, PlayerStats};
+
+use crate::physics::Ball;
+use crate::rooms::Team;
+use crate::ClientId;
+
+// Ворота - прямоугольники у левого и правого края поля
+pub const GOAL_TOP: f64 = 225.0;
+pub const GOAL_BOTTOM: f64 = 375.0;
+pub const GOAL_DEPTH: f64 = 40.0;
+
+/// How a match in a room is timed; shared by every room on the server.
+#[derive(Debug, Clone, Copy)]
+pub struct MatchSettings {
+    /// Total playing time in seconds, split evenly between halves.
+    pub duration_secs: f64,
+    /// Play two halves and swap sides in between.
+    pub halftime: bool,
+}
+
+/// What the clock did during one `advance_clock` step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockEvent {
+    /// A whole second passed; clients get a `Clock` update.
+    Second,
+    /// The first half ended and sides were swapped.
+    HalfTime,
+    /// The match is over and the room is back in the lobby.
+    FullTime,
+}
+
+/// Score, sides, clock and per-player stats of the match played in a room.
+#[derive(Debug, Clone)]
+pub struct MatchState {
+    pub settings: MatchSettings,
+    pub phase: MatchPhase,
+    /// Goals per team, indexed by `Team::index`.
+    pub score: [u32; 2],
+    /// Team defending the goal on the left edge.
+    pub left_side: Team,
+    /// 1 or 2 while playing.
+    pub half: u32,
+    /// Seconds left in the current half.
+    pub remaining: f64,
+    pub stats: HashMap<ClientId, PlayerStats>,
+}
+
+impl MatchState {
+    pub fn new(settings: MatchSettings) -> Self {
+        MatchState {
+            settings,
+            phase: MatchPhase::Lobby,
+            score: [0, 0],
+            left_side: Team::Left,
+            half: 0,
+            remaining: 0.0,
+            stats: HashMap::new(),
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.phase == MatchPhase::Playing
+    }
+
+    fn half_duration(&self) -> f64 {
+        if self.settings.halftime {
+            self.settings.duration_secs / 2.0
+        } else {
+            self.settings.duration_secs
+        }
+    }
+
+    /// Starts a fresh match with the given team assignment.
+    pub fn start(&mut self, teams: &HashMap<ClientId, Team>) {
+        self.phase = MatchPhase::Playing;
+        self.score = [0, 0];
+        self.left_side = Team::Left;
+        self.half = 1;
+        self.remaining = self.half_duration();
+        self.stats = teams
+            .iter()
+            .map(|(id, team)| (*id, PlayerStats::new(*team)))
+            .collect();
+    }
+
+    /// Runs the clock down by `dt` seconds while a match is being played.
+    pub fn advance_clock(&mut self, dt: f64) -> Option<ClockEvent> {
+        if !self.is_playing() {
+            return None;
+        }
+
+        let before = self.remaining_secs();
+        self.remaining = (self.remaining - dt).max(0.0);
+
+        if self.remaining > 0.0 {
+            return (self.remaining_secs() != before).then_some(ClockEvent::Second);
+        }
+
+        if self.settings.halftime && self.half == 1 {
+            self.half = 2;
+            self.remaining = self.half_duration();
+            self.left_side = self.side_team(false);
+            Some(ClockEvent::HalfTime)
+        } else {
+            self.phase = MatchPhase::Lobby;
+            Some(ClockEvent::FullTime)
+        }
+    }
+
+    /// Whole seconds left in the current half, rounded up.
+    pub fn remaining_secs(&self) -> u32 {
+        self.remaining.ceil() as u32
+    }
+
+    /// `None` on a draw.
+    pub fn winner(&self) -> Option<Team> {
+        match self.score[0].cmp(&self.score[1]) {
+            std::cmp::Ordering::Greater => Some(Team::Left),
+            std::cmp::Ordering::Less => Some(Team::Right),
+            std::cmp::Ordering::Equal => None,
+        }
+    }
+
+    fn player_stats(&mut self, id: ClientId, team: Team) -> &mut PlayerStats {
+        self.stats.entry(id).or_insert_with(|| PlayerStats::new(team))
+    }
+
+    /// Gives a player who joins mid-match a line in the final results.
+    pub fn add_player(&mut self, id: ClientId, team: Team) {
+        if self.is_playing() {
+            self.player_stats(id, team);
+        }
+    }
+
+    pub fn record_kick(&mut self, id: ClientId, team: Team) {
+        if self.is_playing() {
+            self.player_stats(id, team).kicks += 1;
+        }
+    }
+
+    pub fn record_touch(&mut self, id: ClientId, team: Team) {
+        if self.is_playing() {
+            self.player_stats(id, team).touches += 1;
+        }
+    }
+
+    fn side_team(&self, left: bool) -> Team {
+        match (left, self.left_side) {
+            (true, team) => team,
+            (false, Team::Left) => Team::Right,
+            (false, Team::Right) => Team::Left,
+        }
+    }
+
+    /// Returns the scoring team if a match is being played and the ball's
+    /// centre is inside either goal.
+    pub fn check_goal(&self, ball: &Ball) -> Option<Team> {
+        if !self.is_playing() || ball.y < GOAL_TOP || ball.y > GOAL_BOTTOM {
+            return None;
+        }
+
+        // Мяч в левых воротах - очко команде, защищающей правые
+        if ball.x <= GOAL_DEPTH {
+            Some(self.side_team(false))
+        } else if ball.x >= FIELD_WIDTH - GOAL_DEPTH {
+            Some(self.side_team(true))
+        } else {
+            None
+        }
+    }
+
+    /// Автогол засчитывается команде, но не игроку
+    pub fn record_goal(&mut self, team: Team, scorer: Option<(ClientId, Team)>) {
+        self.score[team.index() as usize] += 1;
+        if let Some((id, scorer_team)) = scorer {
+            if scorer_team == team {
+                self.player_stats(id, scorer_team).goals += 1;
+            }
+        }
+    }
+
+    /// Spreads each team evenly along a vertical line in its own half.
+    pub fn kickoff_positions(&self, teams: &HashMap<ClientId, Team>) -> Vec<(ClientId, f64, f64)> {
+        let mut positions = Vec::with_capacity(teams.len());
+
+        for left in [true, false] {
+            let team = self.side_team(left);
+            let mut members: Vec<ClientId> = teams
+                .iter()
+                .filter(|(_, member_team)| **member_team == team)
+                .map(|(id, _)| *id)
+                .collect();
+            members.sort_unstable();
+
+            let x = if left { FIELD_WIDTH * 0.25 } else { FIELD_WIDTH * 0.75 };
+            let count = members.len() as f64;
+            for (i, id) in members.into_iter().enumerate() {
+                let y = FIELD_HEIGHT * (i as f64 + 1.0) / (count + 1.0);
+                positions.push((id, x, y));
+            }
+        }
+
+        positions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playing(halftime: bool) -> MatchState {
+        let mut state = MatchState::new(MatchSettings { duration_secs: 4.0, halftime });
+        state.start(&teams());
+        state
+    }
+
+    fn teams() -> HashMap<ClientId, Team> {
+        HashMap::from([(1, Team::Left), (2, Team::Right)])
+    }
+
+    fn ball_at(x: f64, y: f64) -> Ball {
+        Ball { x, y, vel_x: 0.0, vel_y: 0.0 }
+    }
+
+    #[test]
+    fn ball_inside_a_goal_scores_for_the_other_side() {
+        let state = playing(true);
+        assert_eq!(state.check_goal(&ball_at(GOAL_DEPTH, GOAL_TOP)), Some(Team::Right));
+        assert_eq!(state.check_goal(&ball_at(0.0, GOAL_BOTTOM)), Some(Team::Right));
+        assert_eq!(state.check_goal(&ball_at(FIELD_WIDTH - GOAL_DEPTH, 300.0)), Some(Team::Left));
+    }
+
+    #[test]
+    fn ball_at_the_posts_or_in_front_is_no_goal() {
+        let state = playing(true);
+        assert_eq!(state.check_goal(&ball_at(10.0, GOAL_TOP - 0.1)), None);
+        assert_eq!(state.check_goal(&ball_at(10.0, GOAL_BOTTOM + 0.1)), None);
+        assert_eq!(state.check_goal(&ball_at(GOAL_DEPTH + 0.1, 300.0)), None);
+        assert_eq!(state.check_goal(&ball_at(FIELD_WIDTH - GOAL_DEPTH - 0.1, 300.0)), None);
+    }
+
+    #[test]
+    fn no_goals_in_the_lobby() {
+        let state = MatchState::new(MatchSettings { duration_secs: 4.0, halftime: true });
+        assert_eq!(state.check_goal(&ball_at(0.0, 300.0)), None);
+    }
+
+    #[test]
+    fn regular_goal_counts_for_team_and_scorer() {
+        let mut state = playing(true);
+        state.record_goal(Team::Left, Some((1, Team::Left)));
+        assert_eq!(state.score, [1, 0]);
+        assert_eq!(state.stats[&1].goals, 1);
+        assert_eq!(state.winner(), Some(Team::Left));
+    }
+
+    #[test]
+    fn own_goal_counts_for_team_only() {
+        let mut state = playing(true);
+        state.record_goal(Team::Right, Some((1, Team::Left)));
+        state.record_goal(Team::Left, None);
+        assert_eq!(state.score, [1, 1]);
+        assert_eq!(state.stats[&1].goals, 0);
+        assert_eq!(state.stats[&2].goals, 0);
+        assert_eq!(state.winner(), None);
+    }
+
+    #[test]
+    fn clock_reports_whole_seconds_then_halftime_then_fulltime() {
+        let mut state = playing(true);
+        assert_eq!((state.half, state.remaining_secs()), (1, 2));
+
+        assert_eq!(state.advance_clock(0.5), None);
+        assert_eq!(state.advance_clock(0.5), Some(ClockEvent::Second));
+        assert_eq!(state.advance_clock(1.0), Some(ClockEvent::HalfTime));
+        assert_eq!((state.half, state.remaining_secs()), (2, 2));
+        assert_eq!(state.left_side, Team::Right);
+        assert!(state.is_playing());
+
+        assert_eq!(state.advance_clock(1.5), Some(ClockEvent::Second));
+        assert_eq!(state.advance_clock(1.0), Some(ClockEvent::FullTime));
+        assert_eq!(state.phase, MatchPhase::Lobby);
+        assert_eq!(state.advance_clock(1.0), None);
+    }
+
+    #[test]
+    fn without_halftime_the_match_is_one_period() {
+        let mut state = playing(false);
+        assert_eq!(state.remaining_secs(), 4);
+        assert_eq!(state.advance_clock(3.5), Some(ClockEvent::Second));
+        assert_eq!(state.advance_clock(0.5), Some(ClockEvent::FullTime));
+        assert_eq!(state.left_side, Team::Left);
+    }
+
+    #[test]
+    fn sides_swap_at_halftime() {
+        let mut state = playing(true);
+        let before = state.kickoff_positions(&teams());
+        assert_eq!(before, [(1, FIELD_WIDTH * 0.25, FIELD_HEIGHT / 2.0), (2, FIELD_WIDTH * 0.75, FIELD_HEIGHT / 2.0)]);
+
+        state.advance_clock(2.0);
+        let after = state.kickoff_positions(&teams());
+        assert_eq!(after, [(2, FIELD_WIDTH * 0.25, FIELD_HEIGHT / 2.0), (1, FIELD_WIDTH * 0.75, FIELD_HEIGHT / 2.0)]);
+        // Левые ворота теперь защищает Right: мяч в них - очко Left
+        assert_eq!(state.check_goal(&ball_at(0.0, 300.0)), Some(Team::Left));
+    }
+
+    #[test]
+    fn kickoff_spreads_a_team_along_its_line() {
+        let state = playing(true);
+        let teams = HashMap::from([(3, Team::Left), (1, Team::Left), (2, Team::Right)]);
+        let positions = state.kickoff_positions(&teams);
+        assert_eq!(positions[..2], [(1, FIELD_WIDTH * 0.25, FIELD_HEIGHT / 3.0), (3, FIELD_WIDTH * 0.25, FIELD_HEIGHT * 2.0 / 3.0)]);
+    }
+}
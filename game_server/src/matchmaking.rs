@@ -0,0 +1,120 @@
+use std::collections::VecDeque;
+
+use crate::rooms::Team;
+use crate::ClientId;
+
+/// Players waiting for a match, in arrival order.
+///
+/// Lock order: the queue is locked before the room registry and `Clients`.
+#[derive(Debug)]
+pub struct MatchQueue {
+    waiting: VecDeque<ClientId>,
+    team_size: usize,
+    matches_formed: u32,
+}
+
+impl MatchQueue {
+    pub fn new(team_size: usize) -> Self {
+        MatchQueue {
+            waiting: VecDeque::new(),
+            team_size,
+            matches_formed: 0,
+        }
+    }
+
+    pub fn team_size(&self) -> usize {
+        self.team_size
+    }
+
+    /// Returns `false` if the client is already queued.
+    pub fn enqueue(&mut self, client_id: ClientId) -> bool {
+        if self.waiting.contains(&client_id) {
+            return false;
+        }
+        self.waiting.push_back(client_id);
+        true
+    }
+
+    pub fn remove(&mut self, client_id: ClientId) -> bool {
+        let before = self.waiting.len();
+        self.waiting.retain(|id| *id != client_id);
+        self.waiting.len() != before
+    }
+
+    /// Takes the longest-waiting players for one match, alternating them
+    /// between teams, along with a name for the match room.
+    pub fn pop_match(&mut self) -> Option<(String, Vec<(ClientId, Team)>)> {
+        let match_size = self.team_size * 2;
+        if self.waiting.len() < match_size {
+            return None;
+        }
+
+        self.matches_formed += 1;
+        let name = format!("match-{}", self.matches_formed);
+        let players = self
+            .waiting
+            .drain(..match_size)
+            .enumerate()
+            .map(|(i, id)| (id, if i % 2 == 0 { Team::Left } else { Team::Right }))
+            .collect();
+
+        Some((name, players))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_client_is_queued_once() {
+        let mut queue = MatchQueue::new(1);
+        assert!(queue.enqueue(1));
+        assert!(!queue.enqueue(1));
+        assert!(queue.pop_match().is_none());
+    }
+
+    #[test]
+    fn removed_client_is_not_matched() {
+        let mut queue = MatchQueue::new(1);
+        queue.enqueue(1);
+        queue.enqueue(2);
+        assert!(queue.remove(1));
+        assert!(!queue.remove(1));
+        assert!(queue.pop_match().is_none());
+
+        queue.enqueue(3);
+        let (_, players) = queue.pop_match().unwrap();
+        assert_eq!(players, [(2, Team::Left), (3, Team::Right)]);
+    }
+
+    #[test]
+    fn waits_for_two_full_teams() {
+        let mut queue = MatchQueue::new(2);
+        for id in 1..=3 {
+            queue.enqueue(id);
+            assert!(queue.pop_match().is_none());
+        }
+        queue.enqueue(4);
+        assert!(queue.pop_match().is_some());
+    }
+
+    #[test]
+    fn longest_waiting_players_alternate_between_teams() {
+        let mut queue = MatchQueue::new(2);
+        for id in [5, 3, 8, 1, 9] {
+            queue.enqueue(id);
+        }
+        let (name, players) = queue.pop_match().unwrap();
+        assert_eq!(name, "match-1");
+        assert_eq!(players, [(5, Team::Left), (3, Team::Right), (8, Team::Left), (1, Team::Right)]);
+
+        // Девятый остаётся в очереди до следующего матча
+        for id in [10, 11, 12] {
+            queue.enqueue(id);
+        }
+        let (name, players) = queue.pop_match().unwrap();
+        assert_eq!(name, "match-2");
+        assert_eq!(players[0], (9, Team::Left));
+    }
+}
@@ -0,0 +1,336 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tokio::sync::Notify;
+use tokio_tungstenite::tungstenite::Message;
+
+/// Queued messages beyond which a client counts as too slow.
+const MAX_QUEUED: usize = 256;
+/// How long a client may stay over `MAX_QUEUED` before it is disconnected.
+const OVERFLOW_GRACE: Duration = Duration::from_secs(5);
+/// Past this many queued messages the client is disconnected at once.
+const HARD_LIMIT: usize = 4 * MAX_QUEUED;
+
+/// What the game and connection tasks hand to a client's socket task.
+pub enum Outgoing {
+    Message(Message),
+    /// End of a tick: a batching client gets everything queued since the
+    /// previous flush as one frame.
+    Flush,
+}
+
+#[derive(PartialEq, Eq)]
+enum Kind {
+    /// Events the client must see, in order (Joined, Left, GoalScored...).
+    Reliable,
+    /// World state superseded by the next one (Snapshot, Delta).
+    State,
+    Flush,
+}
+
+#[derive(Default)]
+struct Queue {
+    items: VecDeque<(Kind, Outgoing)>,
+    /// Messages in `items`, flushes not counted.
+    messages: usize,
+    over_since: Option<Instant>,
+    overflowed: bool,
+    /// The socket task gave up on the client.
+    aborted: bool,
+    closed: bool,
+}
+
+impl Queue {
+    fn check_limit(&mut self) {
+        if self.messages <= MAX_QUEUED {
+            self.over_since = None;
+            return;
+        }
+        let over_since = *self.over_since.get_or_insert_with(Instant::now);
+        if self.messages > HARD_LIMIT || over_since.elapsed() > OVERFLOW_GRACE {
+            self.overflowed = true;
+        }
+    }
+}
+
+/// Bounded queue between the game and a client's socket task. Only the
+/// newest state message is kept, so a slow client skips snapshots instead
+/// of falling further behind; if reliable events still pile up past the
+/// limit, the client is marked as overflowed and should be disconnected.
+#[derive(Default)]
+pub struct Outbound {
+    queue: Mutex<Queue>,
+    ready: Notify,
+    overflow: Notify,
+    abort: Notify,
+}
+
+impl Outbound {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues an event; `false` if the client is gone or overflowed.
+    pub fn push(&self, message: Message) -> bool {
+        self.enqueue(Kind::Reliable, Outgoing::Message(message))
+    }
+
+    /// Queues a Snapshot or Delta in place of any not sent yet.
+    pub fn push_state(&self, message: Message) -> bool {
+        self.enqueue(Kind::State, Outgoing::Message(message))
+    }
+
+    pub fn flush(&self) {
+        self.enqueue(Kind::Flush, Outgoing::Flush);
+    }
+
+    fn enqueue(&self, kind: Kind, item: Outgoing) -> bool {
+        let mut queue = self.queue.lock().unwrap();
+        if queue.closed || queue.overflowed || queue.aborted {
+            return false;
+        }
+
+        match kind {
+            // Подряд идущие Flush ничего не добавляют. В пустую очередь Flush
+            // кладётся: задача отправки может держать неотправленный пакет
+            Kind::Flush if matches!(queue.items.back(), Some((Kind::Flush, _))) => {}
+            Kind::Flush => queue.items.push_back((kind, item)),
+            Kind::State => {
+                // Вместе со старым состоянием убираются и оказавшиеся рядом Flush,
+                // иначе у нечитающего клиента они копились бы без счёта
+                let mut after_flush = false;
+                queue.items.retain(|(kind, _)| {
+                    let keep = match kind {
+                        Kind::State => false,
+                        Kind::Flush => !after_flush,
+                        Kind::Reliable => true,
+                    };
+                    if keep {
+                        after_flush = *kind == Kind::Flush;
+                    }
+                    keep
+                });
+                queue.messages = queue.items.iter().filter(|(kind, _)| *kind != Kind::Flush).count();
+                queue.items.push_back((kind, item));
+                queue.messages += 1;
+            }
+            Kind::Reliable => {
+                queue.items.push_back((kind, item));
+                queue.messages += 1;
+            }
+        }
+        queue.check_limit();
+
+        let overflowed = queue.overflowed;
+        drop(queue);
+        self.ready.notify_one();
+        if overflowed {
+            self.overflow.notify_one();
+        }
+        !overflowed
+    }
+
+    /// Next item for the socket task; `None` once closed and drained.
+    pub async fn pop(&self) -> Option<Outgoing> {
+        loop {
+            {
+                let mut queue = self.queue.lock().unwrap();
+                if let Some((kind, item)) = queue.items.pop_front() {
+                    if kind != Kind::Flush {
+                        queue.messages -= 1;
+                    }
+                    queue.check_limit();
+                    return Some(item);
+                }
+                if queue.closed {
+                    return None;
+                }
+            }
+            self.ready.notified().await;
+        }
+    }
+
+    /// No more messages will be queued; `pop` drains what is left.
+    pub fn close(&self) {
+        self.queue.lock().unwrap().closed = true;
+        self.ready.notify_one();
+    }
+
+    /// Resolves once the client has stayed over the limit for too long.
+    pub async fn overflowed(&self) {
+        loop {
+            if self.queue.lock().unwrap().overflowed {
+                return;
+            }
+            self.overflow.notified().await;
+        }
+    }
+
+    /// Called by the socket task when a send failed or stalled: nothing more
+    /// is queued and `aborted` resolves, so the connection can be dropped.
+    pub fn abort(&self) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.aborted = true;
+        queue.items.clear();
+        queue.messages = 0;
+        drop(queue);
+        self.abort.notify_one();
+    }
+
+    /// Resolves once the socket task has given up on the client.
+    pub async fn aborted(&self) {
+        loop {
+            if self.queue.lock().unwrap().aborted {
+                return;
+            }
+            self.abort.notified().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Message {
+        Message::Text(s.to_string())
+    }
+
+    fn drain(outbound: &Outbound) -> Vec<String> {
+        let mut items = Vec::new();
+        let mut queue = outbound.queue.lock().unwrap();
+        while let Some((_, item)) = queue.items.pop_front() {
+            items.push(match item {
+                Outgoing::Message(msg) => msg.to_string(),
+                Outgoing::Flush => "|".to_string(),
+            });
+        }
+        queue.messages = 0;
+        items
+    }
+
+    #[tokio::test]
+    async fn flush_after_the_queue_ran_dry_is_delivered() {
+        let outbound = Outbound::new();
+        outbound.push(text("RoomList"));
+        assert!(matches!(outbound.pop().await, Some(Outgoing::Message(_))));
+
+        // Задача отправки держит RoomList в пакете и ждёт Flush
+        outbound.flush();
+        assert!(matches!(outbound.pop().await, Some(Outgoing::Flush)));
+    }
+
+    #[test]
+    fn consecutive_flushes_collapse() {
+        let outbound = Outbound::new();
+        outbound.flush();
+        outbound.flush();
+        outbound.push(text("a"));
+        outbound.flush();
+        outbound.flush();
+        assert_eq!(drain(&outbound), ["|", "a", "|"]);
+    }
+
+    #[test]
+    fn newer_state_replaces_unsent_state_but_not_events() {
+        let outbound = Outbound::new();
+        outbound.push(text("Joined"));
+        outbound.push_state(text("Snapshot 1"));
+        outbound.flush();
+        outbound.push_state(text("Snapshot 2"));
+        outbound.push(text("GoalScored"));
+        outbound.push_state(text("Snapshot 3"));
+        assert_eq!(outbound.queue.lock().unwrap().messages, 3);
+        assert_eq!(drain(&outbound), ["Joined", "|", "GoalScored", "Snapshot 3"]);
+    }
+
+    #[test]
+    fn state_alone_never_overflows() {
+        let outbound = Outbound::new();
+        for i in 0..10 * HARD_LIMIT {
+            assert!(outbound.push_state(text(&i.to_string())));
+            outbound.flush();
+        }
+        let queue = outbound.queue.lock().unwrap();
+        assert!(!queue.overflowed);
+        assert_eq!(queue.messages, 1);
+        assert!(queue.items.len() <= 3, "{} items queued", queue.items.len());
+    }
+
+    #[tokio::test]
+    async fn hard_limit_overflows_at_once() {
+        let outbound = Outbound::new();
+        for _ in 0..HARD_LIMIT {
+            assert!(outbound.push(text("event")));
+        }
+        assert!(!outbound.push(text("event")));
+        tokio::time::timeout(Duration::from_secs(1), outbound.overflowed()).await.unwrap();
+        // После переполнения очередь больше ничего не принимает
+        assert!(!outbound.push_state(text("Snapshot")));
+    }
+
+    #[test]
+    fn over_the_limit_for_the_grace_period_overflows() {
+        let outbound = Outbound::new();
+        for _ in 0..=MAX_QUEUED {
+            assert!(outbound.push(text("event")));
+        }
+        let over_since = outbound.queue.lock().unwrap().over_since;
+        assert!(over_since.is_some());
+
+        outbound.queue.lock().unwrap().over_since = Some(Instant::now() - OVERFLOW_GRACE - Duration::from_millis(1));
+        assert!(!outbound.push(text("event")));
+    }
+
+    #[tokio::test]
+    async fn draining_below_the_limit_resets_the_grace_period() {
+        let outbound = Outbound::new();
+        for _ in 0..=MAX_QUEUED {
+            outbound.push(text("event"));
+        }
+        outbound.pop().await;
+        assert!(outbound.queue.lock().unwrap().over_since.is_none());
+    }
+
+    #[tokio::test]
+    async fn close_drains_then_ends() {
+        let outbound = Outbound::new();
+        outbound.push(text("Left"));
+        outbound.close();
+        assert!(!outbound.push(text("late")));
+        assert!(matches!(outbound.pop().await, Some(Outgoing::Message(_))));
+        assert!(outbound.pop().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn abort_stops_queueing_and_wakes_the_reader() {
+        let outbound = std::sync::Arc::new(Outbound::new());
+        let reader = tokio::spawn({
+            let outbound = std::sync::Arc::clone(&outbound);
+            async move { outbound.aborted().await }
+        });
+        outbound.push(text("Joined"));
+        tokio::task::yield_now().await;
+
+        outbound.abort();
+        tokio::time::timeout(Duration::from_secs(1), reader).await.unwrap().unwrap();
+        assert!(!outbound.push(text("Left")));
+        assert!(!outbound.push_state(text("Snapshot")));
+        assert_eq!(outbound.queue.lock().unwrap().items.len(), 0);
+        // Это не переполнение: клиент отключается по другой причине
+        assert!(tokio::time::timeout(Duration::from_millis(10), outbound.overflowed()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn pop_waits_for_a_push() {
+        let outbound = std::sync::Arc::new(Outbound::new());
+        let waiter = tokio::spawn({
+            let outbound = std::sync::Arc::clone(&outbound);
+            async move { outbound.pop().await.is_some() }
+        });
+        tokio::task::yield_now().await;
+        outbound.push(text("Joined"));
+        assert!(tokio::time::timeout(Duration::from_secs(1), waiter).await.unwrap().unwrap());
+    }
+}
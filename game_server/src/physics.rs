@@ -0,0 +1,525 @@
+use std::collections::{HashMap, HashSet};
+
+use york_protocol::snapshot::{FIELD_HEIGHT, FIELD_WIDTH};
+
+use crate::ClientId;
+
+// Размеры объектов совпадают с константами клиента (client_with_wasm.js)
+pub const BALL_RADIUS: f64 = 15.0;
+pub const AVATAR_RADIUS: f64 = 20.0;
+
+// Ball velocity is expressed in pixels per 1/60 s frame, like the client's `updateBall`
+pub const BALL_FRICTION: f64 = 0.96;
+pub const BALL_RESTITUTION: f64 = 0.5;
+pub const KICK_POWER: f64 = 4.0;
+
+/// Player speed in pixels per second, as the client's `PLAYER_SPEED`.
+pub const PLAYER_SPEED: f64 = 120.0;
+
+/// Headroom over `PLAYER_SPEED` before a `Move` counts as too fast.
+const SPEED_TOLERANCE: f64 = 1.25;
+
+/// Distance a `Move` may be off on top of the speed limit, for network
+/// jitter and the tick granularity of the elapsed time. Used slack refills
+/// over one second, so sending many small jumps gains nothing.
+const MOVE_SLACK: f64 = 30.0;
+
+/// Extra reach granted to the kicker to absorb latency between the client's view and ours.
+pub const KICK_REACH_TOLERANCE: f64 = 15.0;
+
+/// Minimum time between two kicks of one player, in seconds; a bit below
+/// the client's 500 ms so that network jitter does not reject honest kicks.
+pub const KICK_COOLDOWN: f64 = 0.4;
+
+/// Below this speed the ball is considered at rest.
+const BALL_REST_SPEED: f64 = 0.01;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Ball {
+    pub x: f64,
+    pub y: f64,
+    pub vel_x: f64,
+    pub vel_y: f64,
+}
+
+impl Ball {
+    pub fn at_center() -> Self {
+        Ball {
+            x: FIELD_WIDTH / 2.0,
+            y: FIELD_HEIGHT / 2.0,
+            vel_x: 0.0,
+            vel_y: 0.0,
+        }
+    }
+
+    // Тот же порядок шагов, что и в клиентском updateBall: движение, трение, отскок от стен
+    fn step(&mut self, dt: f64) {
+        let frames = dt * 60.0;
+
+        self.x += self.vel_x * frames;
+        self.y += self.vel_y * frames;
+
+        let friction = BALL_FRICTION.powf(frames);
+        self.vel_x *= friction;
+        self.vel_y *= friction;
+
+        let (min_x, max_x) = (BALL_RADIUS, FIELD_WIDTH - BALL_RADIUS);
+        let (min_y, max_y) = (BALL_RADIUS, FIELD_HEIGHT - BALL_RADIUS);
+
+        if self.x < min_x {
+            self.x = min_x;
+            self.vel_x = -self.vel_x * BALL_RESTITUTION;
+        } else if self.x > max_x {
+            self.x = max_x;
+            self.vel_x = -self.vel_x * BALL_RESTITUTION;
+        }
+
+        if self.y < min_y {
+            self.y = min_y;
+            self.vel_y = -self.vel_y * BALL_RESTITUTION;
+        } else if self.y > max_y {
+            self.y = max_y;
+            self.vel_y = -self.vel_y * BALL_RESTITUTION;
+        }
+
+        if self.vel_x.hypot(self.vel_y) < BALL_REST_SPEED {
+            self.vel_x = 0.0;
+            self.vel_y = 0.0;
+        }
+    }
+}
+
+/// Last known state of a player, taken from its `Move` messages and
+/// extrapolated on every tick. Velocity is in pixels per second.
+#[derive(Debug, Clone, Copy)]
+pub struct PlayerState {
+    pub x: f64,
+    pub y: f64,
+    pub vel_x: f64,
+    pub vel_y: f64,
+    /// Position of the last accepted `Move` (or spawn/kickoff), and the
+    /// simulated seconds since then, for the speed check.
+    anchor: (f64, f64),
+    anchor_age: f64,
+    /// What is left of `MOVE_SLACK`.
+    slack: f64,
+    /// Seconds until this player may kick again.
+    kick_cooldown: f64,
+}
+
+impl PlayerState {
+    pub fn new() -> Self {
+        // Та же стартовая позиция, что и у клиента при Welcome/Joined
+        PlayerState::at(100.0, 100.0)
+    }
+
+    /// At rest at the given position.
+    pub fn at(x: f64, y: f64) -> Self {
+        PlayerState { x, y, vel_x: 0.0, vel_y: 0.0, anchor: (x, y), anchor_age: 0.0, slack: MOVE_SLACK, kick_cooldown: 0.0 }
+    }
+
+    fn step(&mut self, dt: f64) {
+        self.anchor_age += dt;
+        self.slack = (self.slack + MOVE_SLACK * dt).min(MOVE_SLACK);
+        self.kick_cooldown = (self.kick_cooldown - dt).max(0.0);
+        self.x += self.vel_x * dt;
+        self.y += self.vel_y * dt;
+
+        let (min_x, max_x) = (AVATAR_RADIUS, FIELD_WIDTH - AVATAR_RADIUS);
+        let (min_y, max_y) = (AVATAR_RADIUS, FIELD_HEIGHT - AVATAR_RADIUS);
+
+        if self.x < min_x || self.x > max_x {
+            self.x = self.x.clamp(min_x, max_x);
+            self.vel_x = 0.0;
+        }
+        if self.y < min_y || self.y > max_y {
+            self.y = self.y.clamp(min_y, max_y);
+            self.vel_y = 0.0;
+        }
+    }
+}
+
+/// Result of validating a `Move`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveOutcome {
+    Accepted,
+    /// Applied after clamping to the field, the speed limit or the distance
+    /// the player could have covered; the client needs a `Correction`.
+    Corrected,
+    /// Non-finite values: ignored, the client needs a `Correction`.
+    Rejected,
+}
+
+/// Result of validating a `Kick`; anything but `Accepted` leaves the ball alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KickOutcome {
+    Accepted,
+    /// The kicker is not in this world.
+    UnknownPlayer,
+    OutOfReach,
+    /// Less than `KICK_COOLDOWN` since the player's last accepted kick.
+    Cooldown,
+    /// Zero or non-finite direction.
+    InvalidDirection,
+}
+
+/// Server-owned simulation: the ball plus every connected player.
+#[derive(Debug)]
+pub struct World {
+    pub players: HashMap<ClientId, PlayerState>,
+    pub ball: Ball,
+    /// Player who last kicked the ball, credited with the next goal.
+    pub last_touch: Option<ClientId>,
+    /// Players in contact with the ball after the last step.
+    touching: HashSet<ClientId>,
+}
+
+impl World {
+    pub fn new() -> Self {
+        World {
+            players: HashMap::new(),
+            ball: Ball::at_center(),
+            last_touch: None,
+            touching: HashSet::new(),
+        }
+    }
+
+    pub fn add_player(&mut self, id: ClientId) {
+        self.players.insert(id, PlayerState::new());
+    }
+
+    pub fn remove_player(&mut self, id: ClientId) {
+        self.players.remove(&id);
+        self.touching.remove(&id);
+    }
+
+    /// Applies a client's claimed position and velocity within what the
+    /// player could legally have done since its last accepted move.
+    pub fn apply_move(&mut self, id: ClientId, x: f64, y: f64, vel_x: f64, vel_y: f64) -> MoveOutcome {
+        let player = self.players.entry(id).or_insert_with(PlayerState::new);
+        if ![x, y, vel_x, vel_y].iter().all(|value| value.is_finite()) {
+            return MoveOutcome::Rejected;
+        }
+        let mut outcome = MoveOutcome::Accepted;
+
+        let (clamped_x, clamped_y) = (
+            x.clamp(AVATAR_RADIUS, FIELD_WIDTH - AVATAR_RADIUS),
+            y.clamp(AVATAR_RADIUS, FIELD_HEIGHT - AVATAR_RADIUS),
+        );
+        if (clamped_x, clamped_y) != (x, y) {
+            outcome = MoveOutcome::Corrected;
+        }
+        let (mut x, mut y) = (clamped_x, clamped_y);
+        let (mut vel_x, mut vel_y) = (vel_x, vel_y);
+        let max_speed = PLAYER_SPEED * SPEED_TOLERANCE;
+        let speed = vel_x.hypot(vel_y);
+        if speed > max_speed {
+            vel_x *= max_speed / speed;
+            vel_y *= max_speed / speed;
+            outcome = MoveOutcome::Corrected;
+        }
+
+        // Дальше, чем можно пройти с момента последнего принятого Move, - ставим на предел
+        let (anchor_x, anchor_y) = player.anchor;
+        let reachable = max_speed * player.anchor_age;
+        let max_dist = reachable + player.slack;
+        let mut dist = (x - anchor_x).hypot(y - anchor_y);
+        if dist > max_dist {
+            x = anchor_x + (x - anchor_x) * max_dist / dist;
+            y = anchor_y + (y - anchor_y) * max_dist / dist;
+            dist = max_dist;
+            outcome = MoveOutcome::Corrected;
+        }
+        let slack = player.slack - (dist - reachable).max(0.0);
+
+        *player = PlayerState { x, y, vel_x, vel_y, anchor: (x, y), anchor_age: 0.0, slack, ..*player };
+        outcome
+    }
+
+    /// Applies a kick impulse if the kicker is within reach of the ball and
+    /// off cooldown. The direction is normalized, so every kick has exactly
+    /// `KICK_POWER` whatever the client sent.
+    pub fn try_kick(&mut self, id: ClientId, dir_x: f64, dir_y: f64) -> KickOutcome {
+        let Some(player) = self.players.get_mut(&id) else {
+            return KickOutcome::UnknownPlayer;
+        };
+
+        let norm = dir_x.hypot(dir_y);
+        if !norm.is_finite() || norm == 0.0 {
+            return KickOutcome::InvalidDirection;
+        }
+
+        let reach = BALL_RADIUS + AVATAR_RADIUS + KICK_REACH_TOLERANCE;
+        let dist = (self.ball.x - player.x).hypot(self.ball.y - player.y);
+        if dist > reach {
+            return KickOutcome::OutOfReach;
+        }
+
+        if player.kick_cooldown > 0.0 {
+            return KickOutcome::Cooldown;
+        }
+        player.kick_cooldown = KICK_COOLDOWN;
+
+        self.ball.vel_x = dir_x / norm * KICK_POWER;
+        self.ball.vel_y = dir_y / norm * KICK_POWER;
+        self.last_touch = Some(id);
+
+        KickOutcome::Accepted
+    }
+
+    /// Puts the ball back to the centre and every listed player at rest at its position.
+    pub fn reset_for_kickoff(&mut self, positions: &[(ClientId, f64, f64)]) {
+        self.ball = Ball::at_center();
+        self.last_touch = None;
+        self.touching.clear();
+
+        for (id, x, y) in positions {
+            if let Some(player) = self.players.get_mut(id) {
+                *player = PlayerState::at(*x, *y);
+            }
+        }
+    }
+
+    /// Advances the simulation and returns the players that came into
+    /// contact with the ball during this step.
+    pub fn step(&mut self, dt: f64) -> Vec<ClientId> {
+        for player in self.players.values_mut() {
+            player.step(dt);
+        }
+        self.ball.step(dt);
+
+        let contact = BALL_RADIUS + AVATAR_RADIUS;
+        let ball = self.ball;
+        let touching: HashSet<ClientId> = self
+            .players
+            .iter()
+            .filter(|(_, player)| (ball.x - player.x).hypot(ball.y - player.y) <= contact)
+            .map(|(id, _)| *id)
+            .collect();
+
+        let mut new_touches: Vec<ClientId> = touching.difference(&self.touching).copied().collect();
+        new_touches.sort_unstable();
+        self.touching = touching;
+        new_touches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: f64 = 1.0 / 60.0;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    fn ball(x: f64, y: f64, vel_x: f64, vel_y: f64) -> Ball {
+        Ball { x, y, vel_x, vel_y }
+    }
+
+    /// World with one player at rest at the given position.
+    fn world_with_player(id: ClientId, x: f64, y: f64) -> World {
+        let mut world = World::new();
+        world.players.insert(id, PlayerState::at(x, y));
+        world
+    }
+
+    #[test]
+    fn ball_moves_then_loses_four_percent_per_frame() {
+        let mut b = ball(400.0, 300.0, 2.0, -1.0);
+        b.step(FRAME);
+        assert!(close(b.x, 402.0) && close(b.y, 299.0));
+        assert!(close(b.vel_x, 2.0 * BALL_FRICTION) && close(b.vel_y, -BALL_FRICTION));
+
+        // Тик 30 Гц - это два кадра клиента
+        let mut b = ball(400.0, 300.0, 2.0, 0.0);
+        b.step(2.0 * FRAME);
+        assert!(close(b.x, 404.0));
+        assert!(close(b.vel_x, 2.0 * BALL_FRICTION * BALL_FRICTION));
+    }
+
+    #[test]
+    fn ball_bounces_off_walls_at_half_speed() {
+        let max_x = FIELD_WIDTH - BALL_RADIUS;
+        let mut b = ball(max_x - 1.0, 300.0, 10.0, 0.0);
+        b.step(FRAME);
+        assert_eq!(b.x, max_x);
+        assert!(close(b.vel_x, -10.0 * BALL_FRICTION * BALL_RESTITUTION));
+
+        let mut b = ball(400.0, BALL_RADIUS + 1.0, 0.0, -10.0);
+        b.step(FRAME);
+        assert_eq!(b.y, BALL_RADIUS);
+        assert!(close(b.vel_y, 10.0 * BALL_FRICTION * BALL_RESTITUTION));
+    }
+
+    #[test]
+    fn ball_far_outside_is_clamped_into_the_field() {
+        let mut b = ball(-500.0, FIELD_HEIGHT + 500.0, 0.0, 0.0);
+        b.step(FRAME);
+        assert_eq!((b.x, b.y), (BALL_RADIUS, FIELD_HEIGHT - BALL_RADIUS));
+    }
+
+    #[test]
+    fn slow_ball_comes_to_rest() {
+        let mut b = ball(400.0, 300.0, 0.005, 0.005);
+        b.step(FRAME);
+        assert_eq!((b.vel_x, b.vel_y), (0.0, 0.0));
+    }
+
+    #[test]
+    fn touch_is_reported_once_per_contact() {
+        let mut world = world_with_player(1, 400.0 - BALL_RADIUS - AVATAR_RADIUS, 300.0);
+        world.players.insert(2, PlayerState::at(100.0, 100.0));
+
+        assert_eq!(world.step(FRAME), vec![1]);
+        // Всё ещё касается - повторно не считается
+        assert_eq!(world.step(FRAME), Vec::<ClientId>::new());
+
+        world.players.insert(1, PlayerState::at(100.0, 500.0));
+        assert_eq!(world.step(FRAME), Vec::<ClientId>::new());
+        world.players.insert(1, PlayerState::at(400.0, 300.0));
+        world.players.insert(2, PlayerState::at(400.0, 320.0));
+        assert_eq!(world.step(FRAME), vec![1, 2]);
+    }
+
+    fn position(world: &World, id: ClientId) -> (f64, f64) {
+        let player = &world.players[&id];
+        (player.x, player.y)
+    }
+
+    #[test]
+    fn non_finite_move_is_rejected_and_ignored() {
+        let mut world = world_with_player(1, 100.0, 100.0);
+        for bad in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            assert_eq!(world.apply_move(1, bad, 100.0, 0.0, 0.0), MoveOutcome::Rejected);
+            assert_eq!(world.apply_move(1, 100.0, 100.0, 0.0, bad), MoveOutcome::Rejected);
+        }
+        let player = world.players[&1];
+        assert_eq!((player.x, player.y, player.vel_x, player.vel_y), (100.0, 100.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn move_outside_the_field_is_clamped() {
+        let mut world = world_with_player(1, AVATAR_RADIUS + 5.0, FIELD_HEIGHT - AVATAR_RADIUS - 5.0);
+        assert_eq!(world.apply_move(1, -10.0, FIELD_HEIGHT + 10.0, 0.0, 0.0), MoveOutcome::Corrected);
+        assert_eq!(position(&world, 1), (AVATAR_RADIUS, FIELD_HEIGHT - AVATAR_RADIUS));
+    }
+
+    #[test]
+    fn velocity_is_capped_at_the_tolerated_speed() {
+        let mut world = world_with_player(1, 100.0, 100.0);
+        assert_eq!(world.apply_move(1, 100.0, 100.0, 300.0, 400.0), MoveOutcome::Corrected);
+        let player = world.players[&1];
+        assert!(close(player.vel_x, 90.0) && close(player.vel_y, 120.0));
+        assert!(close(player.vel_x.hypot(player.vel_y), PLAYER_SPEED * SPEED_TOLERANCE));
+
+        assert_eq!(world.apply_move(1, 100.0, 100.0, 0.0, -150.0), MoveOutcome::Accepted);
+    }
+
+    #[test]
+    fn teleport_stops_at_anchor_plus_slack() {
+        let mut world = world_with_player(1, 100.0, 100.0);
+        assert_eq!(world.apply_move(1, 500.0, 100.0, 0.0, 0.0), MoveOutcome::Corrected);
+        assert_eq!(position(&world, 1), (100.0 + MOVE_SLACK, 100.0));
+    }
+
+    #[test]
+    fn kick_needs_the_ball_within_reach() {
+        let reach = BALL_RADIUS + AVATAR_RADIUS + KICK_REACH_TOLERANCE;
+        let center = Ball::at_center();
+
+        let mut world = world_with_player(1, center.x - reach - 0.1, center.y);
+        assert_eq!(world.try_kick(1, 1.0, 0.0), KickOutcome::OutOfReach);
+        assert_eq!((world.ball.vel_x, world.ball.vel_y), (0.0, 0.0));
+        assert_eq!(world.last_touch, None);
+
+        let mut world = world_with_player(1, center.x - reach, center.y);
+        assert_eq!(world.try_kick(1, 1.0, 0.0), KickOutcome::Accepted);
+        assert_eq!(world.last_touch, Some(1));
+    }
+
+    #[test]
+    fn second_kick_waits_for_the_cooldown() {
+        let mut world = world_with_player(1, 380.0, 300.0);
+        assert_eq!(world.try_kick(1, 1.0, 0.0), KickOutcome::Accepted);
+        world.ball = Ball::at_center();
+        assert_eq!(world.try_kick(1, 1.0, 0.0), KickOutcome::Cooldown);
+        assert_eq!((world.ball.vel_x, world.ball.vel_y), (0.0, 0.0));
+
+        world.step(KICK_COOLDOWN);
+        world.ball = Ball::at_center();
+        assert_eq!(world.try_kick(1, 1.0, 0.0), KickOutcome::Accepted);
+    }
+
+    #[test]
+    fn kick_power_does_not_depend_on_the_direction_length() {
+        for (dir_x, dir_y) in [(3.0, 4.0), (3000.0, 4000.0), (0.003, 0.004)] {
+            let mut world = world_with_player(1, 380.0, 300.0);
+            assert_eq!(world.try_kick(1, dir_x, dir_y), KickOutcome::Accepted);
+            assert!(close(world.ball.vel_x, 2.4) && close(world.ball.vel_y, 3.2));
+            assert!(close(world.ball.vel_x.hypot(world.ball.vel_y), KICK_POWER));
+        }
+    }
+
+    #[test]
+    fn kick_with_a_bad_direction_or_unknown_player_is_ignored() {
+        let mut world = world_with_player(1, 380.0, 300.0);
+        for (dir_x, dir_y) in [(0.0, 0.0), (f64::NAN, 1.0), (f64::INFINITY, 0.0)] {
+            assert_eq!(world.try_kick(1, dir_x, dir_y), KickOutcome::InvalidDirection);
+        }
+        assert_eq!(world.try_kick(2, 1.0, 0.0), KickOutcome::UnknownPlayer);
+        // Неудачные попытки не запускают перезарядку
+        assert_eq!(world.try_kick(1, 1.0, 0.0), KickOutcome::Accepted);
+    }
+
+    #[test]
+    fn slack_is_spent_by_moves_and_refilled_over_time() {
+        let mut world = world_with_player(1, 100.0, 100.0);
+        world.step(0.5);
+
+        // За 0.5 с можно пройти 75, и ещё 10 берётся из запаса
+        assert_eq!(world.apply_move(1, 185.0, 100.0, 0.0, 0.0), MoveOutcome::Accepted);
+        assert_eq!(position(&world, 1), (185.0, 100.0));
+        assert_eq!(world.players[&1].slack, 20.0);
+
+        // Сразу же ещё 30: остатка запаса хватает на 20
+        assert_eq!(world.apply_move(1, 215.0, 100.0, 0.0, 0.0), MoveOutcome::Corrected);
+        assert_eq!(position(&world, 1), (205.0, 100.0));
+        assert_eq!(world.players[&1].anchor, (205.0, 100.0));
+        assert_eq!(world.players[&1].slack, 0.0);
+
+        assert_eq!(world.apply_move(1, 206.0, 100.0, 0.0, 0.0), MoveOutcome::Corrected);
+        assert_eq!(position(&world, 1), (205.0, 100.0));
+
+        // За 0.5 с запас восстанавливается наполовину
+        world.step(0.5);
+        assert_eq!(world.players[&1].slack, MOVE_SLACK / 2.0);
+        assert_eq!(world.apply_move(1, 295.0, 100.0, 0.0, 0.0), MoveOutcome::Accepted);
+        assert_eq!(position(&world, 1), (295.0, 100.0));
+        assert_eq!(world.players[&1].slack, 0.0);
+    }
+
+    #[test]
+    fn corrected_move_is_clamped_along_the_line_to_the_anchor() {
+        let mut world = world_with_player(1, 100.0, 100.0);
+        assert_eq!(world.apply_move(1, 100.0 + 300.0, 100.0 + 400.0, 0.0, 0.0), MoveOutcome::Corrected);
+        let (x, y) = position(&world, 1);
+        assert!(close(x, 118.0) && close(y, 124.0), "({}, {})", x, y);
+        assert_eq!(world.players[&1].anchor, (x, y));
+        assert_eq!(world.players[&1].anchor_age, 0.0);
+    }
+
+    #[test]
+    fn rejected_move_keeps_the_anchor_and_its_age() {
+        let mut world = world_with_player(1, 100.0, 100.0);
+        world.step(0.5);
+        assert_eq!(world.apply_move(1, f64::NAN, 100.0, 0.0, 0.0), MoveOutcome::Rejected);
+
+        let player = world.players[&1];
+        assert_eq!((player.anchor, player.anchor_age, player.slack), ((100.0, 100.0), 0.5, MOVE_SLACK));
+        // Время с прошлого принятого Move не потеряно: 75 + 30
+        assert_eq!(world.apply_move(1, 205.0, 100.0, 0.0, 0.0), MoveOutcome::Accepted);
+        assert_eq!(position(&world, 1), (205.0, 100.0));
+    }
+}
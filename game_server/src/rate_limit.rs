@@ -0,0 +1,165 @@
+use std::time::{Duration, Instant};
+
+use crate::config::RateLimits;
+
+/// Over-limit frames before the client is sent a `Warning`.
+const WARN_AFTER_STRIKES: u32 = 3;
+/// Over-limit frames before the connection is closed.
+const DISCONNECT_AFTER_STRIKES: u32 = 10;
+/// One strike is forgiven per this much time without new ones.
+const STRIKE_DECAY: Duration = Duration::from_secs(1);
+
+/// Refills at `rate` per second up to `capacity`.
+#[derive(Debug)]
+struct TokenBucket {
+    capacity: f64,
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: f64, capacity: f64, now: Instant) -> Self {
+        TokenBucket { capacity, rate, tokens: capacity, last: now }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last = now;
+    }
+
+    fn has(&mut self, amount: f64, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= amount
+    }
+
+    fn take(&mut self, amount: f64) {
+        self.tokens -= amount;
+    }
+}
+
+/// What to do with an incoming frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    /// Over the limit: ignore the frame.
+    Drop,
+    /// Ignore the frame and tell the client it is being limited.
+    Warn,
+    /// Too many strikes: close the connection.
+    Disconnect,
+}
+
+/// Per-connection limits on messages and bytes per second, with a burst of
+/// one second's worth. Frames over the limit are dropped whole and count as
+/// strikes, which escalate from dropping to a warning to disconnecting.
+#[derive(Debug)]
+pub struct RateLimiter {
+    messages: TokenBucket,
+    bytes: TokenBucket,
+    strikes: u32,
+    last_strike: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(limits: &RateLimits) -> Self {
+        let now = Instant::now();
+        let messages = limits.messages_per_sec as f64;
+        let bytes = limits.bytes_per_sec as f64;
+        RateLimiter {
+            messages: TokenBucket::new(messages, messages, now),
+            // Кадр максимального размера должен помещаться целиком
+            bytes: TokenBucket::new(bytes, bytes.max(limits.max_frame_size as f64), now),
+            strikes: 0,
+            last_strike: now,
+        }
+    }
+
+    /// Accounts for a frame of `bytes` carrying `messages` messages.
+    pub fn check(&mut self, bytes: usize, messages: usize) -> Verdict {
+        let now = Instant::now();
+        let (bytes, messages) = (bytes as f64, messages as f64);
+        // Оба ведра проверяются до списания, чтобы отброшенный кадр ничего не тратил
+        if self.messages.has(messages, now) && self.bytes.has(bytes, now) {
+            self.messages.take(messages);
+            self.bytes.take(bytes);
+            return Verdict::Allow;
+        }
+
+        let forgiven = now.saturating_duration_since(self.last_strike).as_secs_f64() / STRIKE_DECAY.as_secs_f64();
+        self.strikes = self.strikes.saturating_sub(forgiven as u32) + 1;
+        self.last_strike = now;
+
+        match self.strikes {
+            DISCONNECT_AFTER_STRIKES.. => Verdict::Disconnect,
+            WARN_AFTER_STRIKES => Verdict::Warn,
+            _ => Verdict::Drop,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(messages_per_sec: u32, bytes_per_sec: usize) -> RateLimits {
+        RateLimits { messages_per_sec, bytes_per_sec, max_frame_size: 1024 }
+    }
+
+    #[test]
+    fn allows_a_burst_of_one_second() {
+        let mut limiter = RateLimiter::new(&limits(10, 100_000));
+        for _ in 0..10 {
+            assert_eq!(limiter.check(10, 1), Verdict::Allow);
+        }
+        assert_eq!(limiter.check(10, 1), Verdict::Drop);
+    }
+
+    #[test]
+    fn strikes_escalate_from_drop_to_warning_to_disconnect() {
+        let mut limiter = RateLimiter::new(&limits(1, 100_000));
+        assert_eq!(limiter.check(10, 1), Verdict::Allow);
+
+        let verdicts: Vec<Verdict> = (0..DISCONNECT_AFTER_STRIKES).map(|_| limiter.check(10, 1)).collect();
+        assert_eq!(verdicts[..2], [Verdict::Drop, Verdict::Drop]);
+        assert_eq!(verdicts[2], Verdict::Warn);
+        assert!(verdicts[3..9].iter().all(|verdict| *verdict == Verdict::Drop));
+        assert_eq!(verdicts[9], Verdict::Disconnect);
+    }
+
+    #[test]
+    fn dropped_frames_do_not_spend_tokens() {
+        let mut limiter = RateLimiter::new(&limits(10, 100));
+        // Не помещается по байтам, сообщения при этом не списываются
+        assert_eq!(limiter.check(2000, 1), Verdict::Drop);
+        for _ in 0..10 {
+            assert_eq!(limiter.check(1, 1), Verdict::Allow);
+        }
+    }
+
+    #[test]
+    fn a_maximum_size_frame_always_fits_an_idle_bucket() {
+        let mut limiter = RateLimiter::new(&limits(10, 100));
+        assert_eq!(limiter.check(1024, 1), Verdict::Allow);
+    }
+
+    #[test]
+    fn tokens_refill_and_strikes_decay_over_time() {
+        let mut limiter = RateLimiter::new(&limits(2, 100_000));
+        limiter.check(1, 2);
+        for _ in 0..5 {
+            assert_ne!(limiter.check(1, 1), Verdict::Allow);
+        }
+        assert_eq!(limiter.strikes, 5);
+
+        // Как будто прошло две секунды: ведро снова полное, старые страйки прощены
+        let earlier = Instant::now() - Duration::from_secs(2);
+        limiter.messages.last = earlier;
+        limiter.bytes.last = earlier;
+        limiter.last_strike -= STRIKE_DECAY * DISCONNECT_AFTER_STRIKES;
+        assert_eq!(limiter.check(1, 2), Verdict::Allow);
+        assert_eq!(limiter.check(1, 1), Verdict::Drop);
+        assert_eq!(limiter.strikes, 1);
+    }
+}
@@ -0,0 +1,341 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+use york_protocol::{RoomInfo, ServerMessage};
+
+use crate::match_state::{MatchSettings, MatchState};
+use crate::physics::World;
+use crate::snapshot::SnapshotHistory;
+use crate::ClientId;
+
+pub use york_protocol::{RoomId, Team};
+
+const MAX_ROOM_NAME_LEN: usize = 32;
+
+/// A match in progress: its own world, snapshot history and members.
+#[derive(Debug)]
+pub struct Room {
+    pub id: RoomId,
+    pub name: String,
+    pub max_players: usize,
+    pub members: BTreeSet<ClientId>,
+    pub teams: HashMap<ClientId, Team>,
+    pub world: World,
+    pub history: SnapshotHistory,
+    pub match_state: MatchState,
+}
+
+impl Room {
+    pub fn is_full(&self) -> bool {
+        self.members.len() >= self.max_players
+    }
+
+    /// Starts a match with the current teams and puts everyone at kickoff
+    /// positions, which are returned.
+    pub fn start_match(&mut self) -> Result<Vec<(ClientId, f64, f64)>, RoomError> {
+        if self.match_state.is_playing() {
+            return Err(RoomError::MatchInProgress);
+        }
+        let both_teams = [Team::Left, Team::Right]
+            .iter()
+            .all(|team| self.teams.values().any(|member_team| member_team == team));
+        if !both_teams {
+            return Err(RoomError::NotEnoughPlayers);
+        }
+
+        self.match_state.start(&self.teams);
+        let positions = self.match_state.kickoff_positions(&self.teams);
+        self.world.reset_for_kickoff(&positions);
+        Ok(positions)
+    }
+
+    pub fn joined_message(&self) -> ServerMessage {
+        ServerMessage::RoomJoined {
+            room_id: self.id,
+            name: self.name.clone(),
+            max_players: self.max_players as u32,
+        }
+    }
+
+    /// Phase, sides, score and team of every member.
+    pub fn match_state_message(&self) -> ServerMessage {
+        let mut teams: Vec<(ClientId, Team)> = self.teams.iter().map(|(id, team)| (*id, *team)).collect();
+        teams.sort_by_key(|(id, _)| *id);
+
+        let state = &self.match_state;
+        ServerMessage::MatchState {
+            phase: state.phase,
+            left_side: state.left_side,
+            score: state.score,
+            teams,
+        }
+    }
+
+    /// Final score, winner and per-player stats of the last match.
+    pub fn match_ended_message(&self) -> ServerMessage {
+        let state = &self.match_state;
+        let mut stats: Vec<_> = state.stats.iter().map(|(id, player)| (*id, *player)).collect();
+        stats.sort_by_key(|(id, _)| *id);
+
+        ServerMessage::MatchEnded {
+            score: state.score,
+            winner: state.winner(),
+            stats,
+        }
+    }
+
+    /// The team with fewer players, `Left` on a tie.
+    fn smaller_team(&self) -> Team {
+        let left = self.teams.values().filter(|team| **team == Team::Left).count();
+        if left * 2 <= self.teams.len() {
+            Team::Left
+        } else {
+            Team::Right
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RoomError {
+    InvalidName,
+    NameTaken,
+    NotFound,
+    Full,
+    AlreadyInRoom,
+    NotInRoom,
+    NotEnoughPlayers,
+    MatchInProgress,
+}
+
+impl fmt::Display for RoomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            RoomError::InvalidName => "invalid room name",
+            RoomError::NameTaken => "room name already taken",
+            RoomError::NotFound => "room not found",
+            RoomError::Full => "room is full",
+            RoomError::AlreadyInRoom => "already in this room",
+            RoomError::NotInRoom => "not in a room",
+            RoomError::NotEnoughPlayers => "both teams need at least one player",
+            RoomError::MatchInProgress => "match already in progress",
+        };
+        f.write_str(reason)
+    }
+}
+
+impl std::error::Error for RoomError {}
+
+/// All rooms on the server plus the global tick counter shared by their game loops.
+///
+/// Lock order: when both are needed, lock the registry before `Clients`.
+#[derive(Debug)]
+pub struct RoomRegistry {
+    rooms: HashMap<RoomId, Room>,
+    next_id: RoomId,
+    max_players_limit: usize,
+    match_settings: MatchSettings,
+    /// Last tick stepped by the game loop; snapshot ticks are unique across rooms.
+    pub tick: u32,
+}
+
+impl RoomRegistry {
+    pub fn new(max_players_limit: usize, match_settings: MatchSettings) -> Self {
+        RoomRegistry {
+            rooms: HashMap::new(),
+            next_id: 1,
+            max_players_limit,
+            match_settings,
+            tick: 0,
+        }
+    }
+
+    /// Creates an empty room; `max_players` is clamped to the server limit.
+    pub fn create(&mut self, name: &str, max_players: usize) -> Result<RoomId, RoomError> {
+        let name = name.trim();
+        if name.is_empty() || name.chars().count() > MAX_ROOM_NAME_LEN {
+            return Err(RoomError::InvalidName);
+        }
+        if self.rooms.values().any(|room| room.name == name) {
+            return Err(RoomError::NameTaken);
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.rooms.insert(id, Room {
+            id,
+            name: name.to_string(),
+            max_players: max_players.clamp(1, self.max_players_limit),
+            members: BTreeSet::new(),
+            teams: HashMap::new(),
+            world: World::new(),
+            history: SnapshotHistory::default(),
+            match_state: MatchState::new(self.match_settings),
+        });
+
+        Ok(id)
+    }
+
+    pub fn join(&mut self, room_id: RoomId, client_id: ClientId) -> Result<&Room, RoomError> {
+        let room = self.rooms.get_mut(&room_id).ok_or(RoomError::NotFound)?;
+        if room.members.contains(&client_id) {
+            return Err(RoomError::AlreadyInRoom);
+        }
+        if room.is_full() {
+            return Err(RoomError::Full);
+        }
+
+        // Команду можно переназначить после входа (матчмейкинг так и делает)
+        let team = room.smaller_team();
+        room.teams.insert(client_id, team);
+        room.match_state.add_player(client_id, team);
+        room.members.insert(client_id);
+        room.world.add_player(client_id);
+        Ok(room)
+    }
+
+    /// Removes the client from the room and drops the room once it is empty.
+    /// Returns the remaining members.
+    pub fn leave(&mut self, room_id: RoomId, client_id: ClientId) -> Vec<ClientId> {
+        let Some(room) = self.rooms.get_mut(&room_id) else {
+            return Vec::new();
+        };
+
+        room.members.remove(&client_id);
+        room.teams.remove(&client_id);
+        room.world.remove_player(client_id);
+
+        if room.members.is_empty() {
+            println!("Room {} ({}) is empty, removing it", room.id, room.name);
+            self.rooms.remove(&room_id);
+            return Vec::new();
+        }
+
+        room.members.iter().copied().collect()
+    }
+
+    pub fn get_mut(&mut self, room_id: RoomId) -> Option<&mut Room> {
+        self.rooms.get_mut(&room_id)
+    }
+
+    pub fn rooms_mut(&mut self) -> impl Iterator<Item = &mut Room> {
+        self.rooms.values_mut()
+    }
+
+    pub fn list(&self) -> Vec<RoomInfo> {
+        let mut list: Vec<RoomInfo> = self
+            .rooms
+            .values()
+            .map(|room| RoomInfo {
+                id: room.id,
+                name: room.name.clone(),
+                players: room.members.len() as u32,
+                max_players: room.max_players as u32,
+            })
+            .collect();
+        list.sort_by_key(|info| info.id);
+        list
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> RoomRegistry {
+        RoomRegistry::new(4, MatchSettings { duration_secs: 60.0, halftime: true })
+    }
+
+    #[test]
+    fn create_validates_and_clamps() {
+        let mut rooms = registry();
+        assert_eq!(rooms.create("  ", 4), Err(RoomError::InvalidName));
+        assert_eq!(rooms.create(&"x".repeat(MAX_ROOM_NAME_LEN + 1), 4), Err(RoomError::InvalidName));
+
+        let id = rooms.create(" arena ", 100).unwrap();
+        assert_eq!(rooms.create("arena", 2), Err(RoomError::NameTaken));
+        assert_eq!(rooms.list(), [RoomInfo { id, name: "arena".to_string(), players: 0, max_players: 4 }]);
+    }
+
+    #[test]
+    fn full_room_rejects_another_player() {
+        let mut rooms = registry();
+        let id = rooms.create("duel", 2).unwrap();
+        rooms.join(id, 1).unwrap();
+        assert_eq!(rooms.join(id, 1).err(), Some(RoomError::AlreadyInRoom));
+        rooms.join(id, 2).unwrap();
+        assert_eq!(rooms.join(id, 3).err(), Some(RoomError::Full));
+    }
+
+    #[test]
+    fn unknown_room_id() {
+        let mut rooms = registry();
+        assert_eq!(rooms.join(42, 1).err(), Some(RoomError::NotFound));
+        assert!(rooms.leave(42, 1).is_empty());
+        assert!(rooms.get_mut(42).is_none());
+    }
+
+    #[test]
+    fn joining_players_fill_the_smaller_team() {
+        let mut rooms = registry();
+        let id = rooms.create("balanced", 4).unwrap();
+        for client in 1..=4 {
+            rooms.join(id, client).unwrap();
+        }
+        let room = rooms.get_mut(id).unwrap();
+        assert_eq!(room.teams[&1], Team::Left);
+        assert_eq!(room.teams[&2], Team::Right);
+        assert_eq!(room.teams[&3], Team::Left);
+        assert_eq!(room.teams[&4], Team::Right);
+
+        // Освободившееся место в левой команде занимает следующий
+        rooms.leave(id, 1);
+        rooms.join(id, 5).unwrap();
+        assert_eq!(rooms.get_mut(id).unwrap().teams[&5], Team::Left);
+    }
+
+    #[test]
+    fn last_player_leaving_removes_the_room() {
+        let mut rooms = registry();
+        let id = rooms.create("short-lived", 4).unwrap();
+        rooms.join(id, 1).unwrap();
+        rooms.join(id, 2).unwrap();
+
+        assert_eq!(rooms.leave(id, 1), [2]);
+        assert!(!rooms.get_mut(id).unwrap().world.players.contains_key(&1));
+        assert!(rooms.leave(id, 2).is_empty());
+        assert!(rooms.list().is_empty());
+        // Имя снова свободно, id не переиспользуется
+        assert_eq!(rooms.create("short-lived", 4), Ok(id + 1));
+    }
+
+    #[test]
+    fn moving_to_a_different_room() {
+        let mut rooms = registry();
+        let first = rooms.create("first", 4).unwrap();
+        let second = rooms.create("second", 4).unwrap();
+        rooms.join(first, 1).unwrap();
+        rooms.join(first, 2).unwrap();
+
+        // Так делает enter_room: сначала вход в новую, потом выход из старой
+        rooms.join(second, 1).unwrap();
+        assert_eq!(rooms.leave(first, 1), [2]);
+
+        let players: Vec<(RoomId, u32)> = rooms.list().iter().map(|info| (info.id, info.players)).collect();
+        assert_eq!(players, [(first, 1), (second, 1)]);
+        assert_eq!(rooms.get_mut(second).unwrap().teams[&1], Team::Left);
+    }
+
+    #[test]
+    fn match_needs_both_teams() {
+        let mut rooms = registry();
+        let id = rooms.create("match", 4).unwrap();
+        rooms.join(id, 1).unwrap();
+        let room = rooms.get_mut(id).unwrap();
+        assert_eq!(room.start_match(), Err(RoomError::NotEnoughPlayers));
+
+        rooms.join(id, 2).unwrap();
+        let room = rooms.get_mut(id).unwrap();
+        assert_eq!(room.start_match().unwrap().len(), 2);
+        assert_eq!(room.start_match(), Err(RoomError::MatchInProgress));
+    }
+}
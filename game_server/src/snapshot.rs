@@ -0,0 +1,96 @@
+use std::collections::VecDeque;
+
+use york_protocol::snapshot::{EntityState, Snapshot};
+
+use crate::physics::World;
+use crate::ClientId;
+
+/// How many past snapshots are kept as possible delta baselines (~1 s at 30 Hz).
+const HISTORY_CAPACITY: usize = 32;
+
+/// Copies the world into a snapshot for clients, players in id order.
+pub fn capture(tick: u32, world: &World) -> Snapshot {
+    let mut players: Vec<(ClientId, EntityState)> = world
+        .players
+        .iter()
+        .map(|(id, player)| (*id, EntityState { x: player.x, y: player.y, vel_x: player.vel_x, vel_y: player.vel_y }))
+        .collect();
+    players.sort_by_key(|(id, _)| *id);
+
+    let ball = &world.ball;
+    Snapshot {
+        tick,
+        ball: EntityState { x: ball.x, y: ball.y, vel_x: ball.vel_x, vel_y: ball.vel_y },
+        players,
+    }
+}
+
+/// Recent snapshots that clients may acknowledge and be diffed against.
+#[derive(Debug, Default)]
+pub struct SnapshotHistory {
+    snapshots: VecDeque<Snapshot>,
+}
+
+impl SnapshotHistory {
+    pub fn push(&mut self, snapshot: Snapshot) {
+        if self.snapshots.len() == HISTORY_CAPACITY {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(snapshot);
+    }
+
+    pub fn get(&self, tick: u32) -> Option<&Snapshot> {
+        self.snapshots.iter().rev().find(|snapshot| snapshot.tick == tick)
+    }
+
+    /// Snapshot to diff against for a client that acknowledged `acked_tick`;
+    /// `None` (no ack yet, or already evicted) means a full `Snapshot`.
+    pub fn baseline(&self, acked_tick: Option<u32>) -> Option<&Snapshot> {
+        acked_tick.and_then(|tick| self.get(tick))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(tick: u32) -> Snapshot {
+        Snapshot { tick, ball: EntityState::default(), players: Vec::new() }
+    }
+
+    fn history(ticks: std::ops::Range<u32>) -> SnapshotHistory {
+        let mut history = SnapshotHistory::default();
+        for tick in ticks {
+            history.push(snapshot(tick));
+        }
+        history
+    }
+
+    #[test]
+    fn keeps_the_last_capacity_snapshots() {
+        let history = history(0..HISTORY_CAPACITY as u32 + 5);
+        assert_eq!(history.snapshots.len(), HISTORY_CAPACITY);
+        assert!(history.get(4).is_none());
+        assert_eq!(history.get(5).map(|s| s.tick), Some(5));
+        assert_eq!(history.get(HISTORY_CAPACITY as u32 + 4).map(|s| s.tick), Some(HISTORY_CAPACITY as u32 + 4));
+    }
+
+    #[test]
+    fn unknown_or_evicted_ack_falls_back_to_a_full_snapshot() {
+        let history = history(0..HISTORY_CAPACITY as u32 + 1);
+        assert!(history.baseline(None).is_none());
+        assert!(history.baseline(Some(0)).is_none());
+        assert!(history.baseline(Some(1000)).is_none());
+        assert_eq!(history.baseline(Some(1)).map(|s| s.tick), Some(1));
+    }
+
+    #[test]
+    fn capture_sorts_players_by_id() {
+        let mut world = World::new();
+        for id in [7, 2, 5] {
+            world.add_player(id);
+        }
+        let ids: Vec<ClientId> = capture(3, &world).players.iter().map(|(id, _)| *id).collect();
+        assert_eq!(ids, [2, 5, 7]);
+    }
+}
@@ -20,10 +20,9 @@ WORK_DIR="$HOME/york-ball-game"
 REPO_DIR="$WORK_DIR/repo"
 WEB_DIR="$WORK_DIR/web"
 WASM_DIR="$WEB_DIR/wasm"
-MSGPACK_WASM_DIR="$WORK_DIR/msgpack_wasm"
 
 echo -e "${YELLOW}1. Создание рабочих директорий${NC}"
-mkdir -p $WORK_DIR $WEB_DIR $WASM_DIR $MSGPACK_WASM_DIR/src
+mkdir -p $WORK_DIR $WEB_DIR $WASM_DIR
 
 echo -e "${YELLOW}2. Проверка и установка необходимых зависимостей${NC}"
 # Установка системных зависимостей
@@ -65,69 +64,14 @@ fi
 echo -e "${YELLOW}4. Анализ структуры проекта${NC}"
 cd $REPO_DIR
 
-# Поиск директории с Cargo.toml для msgpack_wasm
-MSGPACK_CARGO_TOML=$(find . -name "Cargo.toml" | grep -i msgpack)
-if [ -z "$MSGPACK_CARGO_TOML" ]; then
-    # Если не найден, возьмем любой Cargo.toml в репозитории
-    MSGPACK_CARGO_TOML=$(find . -name "Cargo.toml" | head -1)
-fi
-
-if [ -z "$MSGPACK_CARGO_TOML" ]; then
-    echo -e "   ${RED}Не найден файл Cargo.toml для WebAssembly модуля!${NC}"
-    # Создаем Cargo.toml для msgpack_wasm
-    echo -e "   ${BLUE}Создание Cargo.toml для WebAssembly модуля...${NC}"
-    cat > "$MSGPACK_WASM_DIR/Cargo.toml" << 'EOF'
-[package]
-name = "msgpack_wasm"
-version = "0.1.0"
-edition = "2021"
-
-[lib]
-crate-type = ["cdylib", "rlib"]
-
-[dependencies]
-wasm-bindgen = "0.2"
-js-sys = "0.3"
-rmp = "0.8"
-rmp-serde = "1.1.2"
-serde = { version = "1.0", features = ["derive"] }
-serde_json = "1.0"
-
-[profile.release]
-opt-level = 3
-lto = true
-codegen-units = 1
-EOF
-else
-    echo -e "   ${GREEN}Найден Cargo.toml: $MSGPACK_CARGO_TOML${NC}"
-    # Копируем Cargo.toml
-    cp "$REPO_DIR/$MSGPACK_CARGO_TOML" "$MSGPACK_WASM_DIR/Cargo.toml"
-fi
-
-# Поиск lib.rs для msgpack_wasm
-LIB_RS=$(find . -name "lib.rs" | grep -i msgpack)
-if [ -z "$LIB_RS" ]; then
-    # Если не найден, ищем любой lib.rs
-    LIB_RS=$(find . -name "lib.rs" | head -1)
-fi
-
-if [ -z "$LIB_RS" ]; then
-    echo -e "   ${RED}Не найден файл lib.rs для WebAssembly модуля!${NC}"
-    
-    # Используем директорию с предыдущими файлами, если она есть
-    if [ -f "$REPO_DIR/lib.rs" ]; then
-        cp "$REPO_DIR/lib.rs" "$MSGPACK_WASM_DIR/src/lib.rs"
-        echo -e "   ${GREEN}Найден и скопирован файл lib.rs из корня репозитория${NC}"
-    else
-        echo -e "   ${RED}ОШИБКА: Не удалось найти файл lib.rs для WebAssembly модуля!${NC}"
-        echo -e "   ${YELLOW}Убедитесь, что файл lib.rs присутствует в репозитории.${NC}"
-        exit 1
-    fi
-else
-    echo -e "   ${GREEN}Найден lib.rs: $LIB_RS${NC}"
-    # Копируем lib.rs
-    cp "$REPO_DIR/$LIB_RS" "$MSGPACK_WASM_DIR/src/lib.rs"
+# WebAssembly модуль собирается прямо в репозитории: он входит в workspace
+# и зависит от общего крейта york-protocol
+MSGPACK_WASM_DIR="$REPO_DIR/york-ball-game"
+if [ ! -f "$MSGPACK_WASM_DIR/Cargo.toml" ]; then
+    echo -e "   ${RED}ОШИБКА: Не найден крейт york-ball-game в репозитории!${NC}"
+    exit 1
 fi
+echo -e "   ${GREEN}Найден WebAssembly модуль: $MSGPACK_WASM_DIR${NC}"
 
 # Поиск HTML файла
 HTML_FILE=$(find . -name "*.html" | head -1)
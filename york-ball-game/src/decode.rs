@@ -0,0 +1,334 @@
+//! MessagePack → JS decoder behind `decode` and `decode_array_message`.
+//!
+//! Covers every format of the spec:
+//! - nil, bool, float32/64 and str → plain JS values
+//! - integers → `Number`, or `BigInt` outside ±`Number.MAX_SAFE_INTEGER`
+//! - bin → `Uint8Array`
+//! - array → `Array`; map → `Object`, or `Map` if any key is not a string;
+//!   nested to any depth up to `MAX_DEPTH`
+//! - timestamp ext (-1) → `Date`, or the `timestamp_object` form when
+//!   decoding with `precise_timestamps`
+//! - ext types in an `ExtRegistry`, when given → whatever it unpacks them to
+//! - other ext → `{ extType, data }` with `data` as a `Uint8Array`
+
+use js_sys::{Array, BigInt, Date, Map, Object, Reflect, Uint8Array};
+use wasm_bindgen::JsValue;
+use york_protocol::{Timestamp, TIMESTAMP_EXT_TYPE};
+
+use crate::ext::ExtRegistry;
+
+const MSGPACK_NIL: u8 = 0xc0;
+const MSGPACK_FALSE: u8 = 0xc2;
+const MSGPACK_TRUE: u8 = 0xc3;
+const MSGPACK_BIN8: u8 = 0xc4;
+const MSGPACK_BIN16: u8 = 0xc5;
+const MSGPACK_BIN32: u8 = 0xc6;
+const MSGPACK_EXT8: u8 = 0xc7;
+const MSGPACK_EXT16: u8 = 0xc8;
+const MSGPACK_EXT32: u8 = 0xc9;
+const MSGPACK_FLOAT32: u8 = 0xca;
+const MSGPACK_FLOAT64: u8 = 0xcb;
+const MSGPACK_UINT8: u8 = 0xcc;
+const MSGPACK_UINT16: u8 = 0xcd;
+const MSGPACK_UINT32: u8 = 0xce;
+const MSGPACK_UINT64: u8 = 0xcf;
+const MSGPACK_INT8: u8 = 0xd0;
+const MSGPACK_INT16: u8 = 0xd1;
+const MSGPACK_INT32: u8 = 0xd2;
+const MSGPACK_INT64: u8 = 0xd3;
+// fixext 1/2/4/8/16 занимают 0xd4..=0xd8
+const MSGPACK_FIXEXT1: u8 = 0xd4;
+const MSGPACK_FIXEXT16: u8 = 0xd8;
+const MSGPACK_STR8: u8 = 0xd9;
+const MSGPACK_STR16: u8 = 0xda;
+const MSGPACK_STR32: u8 = 0xdb;
+const MSGPACK_ARRAY16: u8 = 0xdc;
+const MSGPACK_ARRAY32: u8 = 0xdd;
+const MSGPACK_MAP16: u8 = 0xde;
+const MSGPACK_MAP32: u8 = 0xdf;
+
+/// Deepest container nesting accepted, so hostile input can't overflow the stack.
+const MAX_DEPTH: usize = 64;
+
+/// `Number.MAX_SAFE_INTEGER`: larger integers are returned as `BigInt`.
+const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+pub(crate) struct Decoder<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    depth: usize,
+    precise_timestamps: bool,
+    registry: Option<&'a ExtRegistry>,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Decoder { buf, pos: 0, depth: 0, precise_timestamps: false, registry: None }
+    }
+
+    pub fn with_registry(mut self, registry: &'a ExtRegistry) -> Self {
+        self.registry = Some(registry);
+        self
+    }
+
+    /// Timestamps become `timestamp_object`s instead of a `Date`, which only
+    /// keeps milliseconds.
+    pub fn precise_timestamps(mut self) -> Self {
+        self.precise_timestamps = true;
+        self
+    }
+
+    /// Errors if anything is left after the last decoded value.
+    pub fn finish(&self) -> Result<(), JsValue> {
+        match self.buf.len() - self.pos {
+            0 => Ok(()),
+            extra => Err(JsValue::from_str(&format!("{} trailing bytes after message", extra))),
+        }
+    }
+
+    /// Reads an array header of any width.
+    pub fn array_len(&mut self) -> Result<u32, JsValue> {
+        match self.u8()? {
+            m @ 0x90..=0x9f => Ok((m & 0x0f) as u32),
+            MSGPACK_ARRAY16 => Ok(self.be::<2>()? as u32),
+            MSGPACK_ARRAY32 => Ok(self.be::<4>()? as u32),
+            m => Err(marker_error("array", m)),
+        }
+    }
+
+    /// Reads a string of any width.
+    pub fn str(&mut self) -> Result<&'a str, JsValue> {
+        let len = match self.u8()? {
+            m @ 0xa0..=0xbf => (m & 0x1f) as usize,
+            MSGPACK_STR8 => self.be::<1>()? as usize,
+            MSGPACK_STR16 => self.be::<2>()? as usize,
+            MSGPACK_STR32 => self.be::<4>()? as usize,
+            m => return Err(marker_error("string", m)),
+        };
+        self.utf8(len)
+    }
+
+    /// Reads one value, including everything nested in it.
+    pub fn value(&mut self) -> Result<JsValue, JsValue> {
+        let marker = self.u8()?;
+        let value = match marker {
+            0x00..=0x7f => JsValue::from_f64(marker as f64),
+            0x80..=0x8f => self.map((marker & 0x0f) as usize)?,
+            0x90..=0x9f => self.array((marker & 0x0f) as usize)?,
+            0xa0..=0xbf => JsValue::from_str(self.utf8((marker & 0x1f) as usize)?),
+            MSGPACK_NIL => JsValue::null(),
+            MSGPACK_FALSE => JsValue::FALSE,
+            MSGPACK_TRUE => JsValue::TRUE,
+            MSGPACK_BIN8 | MSGPACK_BIN16 | MSGPACK_BIN32 => {
+                let len = self.be_len(marker - MSGPACK_BIN8)?;
+                Uint8Array::from(self.take(len)?).into()
+            }
+            MSGPACK_EXT8 | MSGPACK_EXT16 | MSGPACK_EXT32 => {
+                let len = self.be_len(marker - MSGPACK_EXT8)?;
+                self.ext(len)?
+            }
+            MSGPACK_FLOAT32 => JsValue::from_f64(f32::from_bits(self.be::<4>()? as u32) as f64),
+            MSGPACK_FLOAT64 => JsValue::from_f64(f64::from_bits(self.be::<8>()?)),
+            MSGPACK_UINT8 => JsValue::from_f64(self.be::<1>()? as f64),
+            MSGPACK_UINT16 => JsValue::from_f64(self.be::<2>()? as f64),
+            MSGPACK_UINT32 => JsValue::from_f64(self.be::<4>()? as f64),
+            MSGPACK_UINT64 => uint_to_js(self.be::<8>()?),
+            MSGPACK_INT8 => JsValue::from_f64(self.be::<1>()? as i8 as f64),
+            MSGPACK_INT16 => JsValue::from_f64(self.be::<2>()? as i16 as f64),
+            MSGPACK_INT32 => JsValue::from_f64(self.be::<4>()? as i32 as f64),
+            MSGPACK_INT64 => int_to_js(self.be::<8>()? as i64),
+            MSGPACK_FIXEXT1..=MSGPACK_FIXEXT16 => self.ext(1 << (marker - MSGPACK_FIXEXT1))?,
+            MSGPACK_STR8 => {
+                let len = self.be::<1>()? as usize;
+                JsValue::from_str(self.utf8(len)?)
+            }
+            MSGPACK_STR16 => {
+                let len = self.be::<2>()? as usize;
+                JsValue::from_str(self.utf8(len)?)
+            }
+            MSGPACK_STR32 => {
+                let len = self.be::<4>()? as usize;
+                JsValue::from_str(self.utf8(len)?)
+            }
+            MSGPACK_ARRAY16 => {
+                let len = self.be::<2>()? as usize;
+                self.array(len)?
+            }
+            MSGPACK_ARRAY32 => {
+                let len = self.be::<4>()? as usize;
+                self.array(len)?
+            }
+            MSGPACK_MAP16 => {
+                let len = self.be::<2>()? as usize;
+                self.map(len)?
+            }
+            MSGPACK_MAP32 => {
+                let len = self.be::<4>()? as usize;
+                self.map(len)?
+            }
+            0xe0..=0xff => JsValue::from_f64(marker as i8 as f64),
+            // 0xc1 зарезервирован спецификацией
+            _ => return Err(marker_error("value", marker)),
+        };
+        Ok(value)
+    }
+
+    fn array(&mut self, len: usize) -> Result<JsValue, JsValue> {
+        self.enter(len)?;
+        let array = Array::new();
+        for _ in 0..len {
+            array.push(&self.value()?);
+        }
+        self.depth -= 1;
+        Ok(array.into())
+    }
+
+    /// A plain `Object` when every key is a string, otherwise a `Map` so
+    /// that keys like `1` and `"1"` stay distinct.
+    fn map(&mut self, len: usize) -> Result<JsValue, JsValue> {
+        self.enter(len.saturating_mul(2))?;
+        let mut entries = Vec::with_capacity(len);
+        for _ in 0..len {
+            let key = self.value()?;
+            let value = self.value()?;
+            entries.push((key, value));
+        }
+        self.depth -= 1;
+
+        if entries.iter().all(|(key, _)| key.is_string()) {
+            let object = Object::new();
+            for (key, value) in &entries {
+                set_own_property(&object, key, value)?;
+            }
+            Ok(object.into())
+        } else {
+            let map = Map::new();
+            for (key, value) in &entries {
+                map.set(key, value);
+            }
+            Ok(map.into())
+        }
+    }
+
+    fn ext(&mut self, len: usize) -> Result<JsValue, JsValue> {
+        let ext_type = self.be::<1>()? as i8;
+        if ext_type == TIMESTAMP_EXT_TYPE {
+            let timestamp = Timestamp::from_ext_data(self.take(len)?)
+                .map_err(|e| JsValue::from_str(&e.to_string()))?;
+            return self.timestamp(timestamp);
+        }
+        let bytes = self.take(len)?;
+        if let Some(registry) = self.registry {
+            if let Some(value) = registry.unpack(ext_type, bytes)? {
+                return Ok(value);
+            }
+        }
+        let data = Uint8Array::from(bytes);
+        let object = Object::new();
+        Reflect::set(&object, &JsValue::from_str("extType"), &JsValue::from_f64(ext_type as f64))?;
+        Reflect::set(&object, &JsValue::from_str("data"), &data)?;
+        Ok(object.into())
+    }
+
+    fn timestamp(&self, timestamp: Timestamp) -> Result<JsValue, JsValue> {
+        if !self.precise_timestamps {
+            return Ok(Date::new(&JsValue::from_f64(timestamp.as_millis())).into());
+        }
+        timestamp_object(timestamp)
+    }
+
+    /// Checks depth and that `elements` values could still fit in the buffer
+    /// (each takes at least one byte), before anything is allocated for them.
+    fn enter(&mut self, elements: usize) -> Result<(), JsValue> {
+        if self.depth == MAX_DEPTH {
+            return Err(JsValue::from_str("MessagePack nesting too deep"));
+        }
+        if elements > self.buf.len() - self.pos {
+            return Err(JsValue::from_str("Container length out of bounds"));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// Length that follows a bin/ext marker; `width` is 0, 1 or 2 for the
+    /// 8, 16 and 32 bit forms.
+    fn be_len(&mut self, width: u8) -> Result<usize, JsValue> {
+        let len = match width {
+            0 => self.be::<1>()?,
+            1 => self.be::<2>()?,
+            _ => self.be::<4>()?,
+        };
+        Ok(len as usize)
+    }
+
+    fn utf8(&mut self, len: usize) -> Result<&'a str, JsValue> {
+        std::str::from_utf8(self.take(len)?)
+            .map_err(|e| JsValue::from_str(&format!("Invalid UTF-8: {}", e)))
+    }
+
+    fn u8(&mut self) -> Result<u8, JsValue> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// Big-endian unsigned integer of `N` bytes.
+    fn be<const N: usize>(&mut self) -> Result<u64, JsValue> {
+        Ok(self.take(N)?.iter().fold(0, |n, &byte| (n << 8) | byte as u64))
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], JsValue> {
+        if len > self.buf.len() - self.pos {
+            return Err(JsValue::from_str("Unexpected end of data"));
+        }
+        let bytes = &self.buf[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+}
+
+fn uint_to_js(n: u64) -> JsValue {
+    if n <= MAX_SAFE_INTEGER {
+        JsValue::from_f64(n as f64)
+    } else {
+        BigInt::from(n).into()
+    }
+}
+
+fn int_to_js(n: i64) -> JsValue {
+    if n.unsigned_abs() <= MAX_SAFE_INTEGER {
+        JsValue::from_f64(n as f64)
+    } else {
+        BigInt::from(n).into()
+    }
+}
+
+/// `{ extType: -1, data, seconds, nanoseconds }`: `encode` writes it back as
+/// the same timestamp ext from `extType` and `data`, the other two fields are
+/// there for reading.
+pub(crate) fn timestamp_object(timestamp: Timestamp) -> Result<JsValue, JsValue> {
+    let object = Object::new();
+    Reflect::set(&object, &JsValue::from_str("extType"), &JsValue::from_f64(TIMESTAMP_EXT_TYPE as f64))?;
+    Reflect::set(&object, &JsValue::from_str("data"), &Uint8Array::from(timestamp.to_ext_data().as_slice()))?;
+    Reflect::set(&object, &JsValue::from_str("seconds"), &int_to_js(timestamp.seconds))?;
+    Reflect::set(&object, &JsValue::from_str("nanoseconds"), &JsValue::from_f64(timestamp.nanoseconds as f64))?;
+    Ok(object.into())
+}
+
+/// Sets `key` as an own data property. `Reflect::set` with `"__proto__"`
+/// would hit the inherited setter and replace the object's prototype instead.
+fn set_own_property(object: &Object, key: &JsValue, value: &JsValue) -> Result<(), JsValue> {
+    if key.as_string().as_deref() != Some("__proto__") {
+        Reflect::set(object, key, value)?;
+        return Ok(());
+    }
+    let descriptor = Object::new();
+    Reflect::set(&descriptor, &JsValue::from_str("value"), value)?;
+    for flag in ["writable", "enumerable", "configurable"] {
+        Reflect::set(&descriptor, &JsValue::from_str(flag), &JsValue::TRUE)?;
+    }
+    Reflect::define_property(object, key, &descriptor)?;
+    Ok(())
+}
+
+fn marker_error(expected: &str, marker: u8) -> JsValue {
+    JsValue::from_str(&format!("Expected {}, found marker 0x{:02x}", expected, marker))
+}
@@ -0,0 +1,178 @@
+//! JS → MessagePack encoder behind `encode`; the inverse of `decode::Decoder`.
+//!
+//! - `null`/`undefined` → nil (object properties set to `undefined` are skipped)
+//! - safe integers → the smallest int format, other numbers (NaN and
+//!   ±Infinity included) → float64
+//! - `BigInt` → int64/uint64
+//! - typed arrays, `DataView` and `ArrayBuffer` → bin with their raw bytes
+//! - `Map` → map with any keys, plain objects → map with string keys
+//! - `Date` → timestamp ext (-1) in its smallest form
+//! - `{ extType, data }` → ext
+//! - instances of classes in an `ExtRegistry`, when given → their ext type
+
+use js_sys::{Array, ArrayBuffer, Date, Map, Object, Reflect, Uint8Array};
+use wasm_bindgen::{JsCast, JsValue};
+use york_protocol::{Timestamp, TIMESTAMP_EXT_TYPE};
+
+use crate::ext::ExtRegistry;
+
+/// Deepest container nesting written; also stops on reference cycles.
+const MAX_DEPTH: usize = 64;
+
+pub(crate) struct Encoder<'r> {
+    buf: Vec<u8>,
+    depth: usize,
+    registry: Option<&'r ExtRegistry>,
+}
+
+impl<'r> Encoder<'r> {
+    pub fn new() -> Self {
+        Encoder { buf: Vec::new(), depth: 0, registry: None }
+    }
+
+    pub fn with_registry(mut self, registry: &'r ExtRegistry) -> Self {
+        self.registry = Some(registry);
+        self
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+
+    pub fn value(&mut self, value: &JsValue) -> Result<(), JsValue> {
+        if value.is_null() || value.is_undefined() {
+            rmp::encode::write_nil(&mut self.buf).map_err(write_error)?;
+        } else if let Some(b) = value.as_bool() {
+            rmp::encode::write_bool(&mut self.buf, b).map_err(write_error)?;
+        } else if let Some(n) = value.as_f64() {
+            self.number(n)?;
+        } else if value.is_bigint() {
+            self.bigint(value)?;
+        } else if let Some(s) = value.as_string() {
+            rmp::encode::write_str(&mut self.buf, &s).map_err(write_error)?;
+        } else if let Some((ext_type, data)) = self.registered(value)? {
+            self.ext(ext_type, &data)?;
+        } else if ArrayBuffer::is_view(value) {
+            let buffer = Reflect::get(value, &JsValue::from_str("buffer"))?;
+            let offset = Reflect::get(value, &JsValue::from_str("byteOffset"))?.as_f64().unwrap_or(0.0);
+            let length = Reflect::get(value, &JsValue::from_str("byteLength"))?.as_f64().unwrap_or(0.0);
+            let bytes = Uint8Array::new_with_byte_offset_and_length(&buffer, offset as u32, length as u32);
+            self.bin(&bytes)?;
+        } else if value.is_instance_of::<ArrayBuffer>() {
+            self.bin(&Uint8Array::new(value))?;
+        } else if let Some(date) = value.dyn_ref::<Date>() {
+            self.date(date)?;
+        } else if Array::is_array(value) {
+            let array = Array::from(value);
+            self.enter()?;
+            rmp::encode::write_array_len(&mut self.buf, array.length()).map_err(write_error)?;
+            for item in array.iter() {
+                self.value(&item)?;
+            }
+            self.depth -= 1;
+        } else if let Some(map) = value.dyn_ref::<Map>() {
+            let mut entries = Vec::with_capacity(map.size() as usize);
+            map.for_each(&mut |value, key| entries.push((key, value)));
+            self.entries(&entries)?;
+        } else if let Some(object) = value.dyn_ref::<Object>() {
+            if let Some((ext_type, data)) = ext_parts(object)? {
+                self.ext(ext_type, &data.to_vec())?;
+            } else {
+                let mut entries = Vec::new();
+                for key in Object::keys(object).iter() {
+                    let item = Reflect::get(object, &key)?;
+                    if !item.is_undefined() {
+                        entries.push((key, item));
+                    }
+                }
+                self.entries(&entries)?;
+            }
+        } else {
+            return Err(JsValue::from_str("Unsupported JavaScript value type"));
+        }
+        Ok(())
+    }
+
+    fn registered(&self, value: &JsValue) -> Result<Option<(i8, Vec<u8>)>, JsValue> {
+        match self.registry {
+            Some(registry) => registry.pack(value),
+            None => Ok(None),
+        }
+    }
+
+    fn number(&mut self, n: f64) -> Result<(), JsValue> {
+        // Number.isSafeInteger; -0 остаётся float, чтобы не потерять знак
+        let safe_integer = n.fract() == 0.0 && n.abs() <= 9_007_199_254_740_991.0;
+        if safe_integer && !(n == 0.0 && n.is_sign_negative()) {
+            if n >= 0.0 {
+                rmp::encode::write_uint(&mut self.buf, n as u64).map_err(write_error)?;
+            } else {
+                rmp::encode::write_sint(&mut self.buf, n as i64).map_err(write_error)?;
+            }
+        } else {
+            rmp::encode::write_f64(&mut self.buf, n).map_err(write_error)?;
+        }
+        Ok(())
+    }
+
+    fn bigint(&mut self, value: &JsValue) -> Result<(), JsValue> {
+        if let Ok(n) = i64::try_from(value.clone()) {
+            rmp::encode::write_i64(&mut self.buf, n).map_err(write_error)?;
+        } else if let Ok(n) = u64::try_from(value.clone()) {
+            rmp::encode::write_u64(&mut self.buf, n).map_err(write_error)?;
+        } else {
+            return Err(JsValue::from_str("BigInt does not fit in 64 bits"));
+        }
+        Ok(())
+    }
+
+    fn bin(&mut self, bytes: &Uint8Array) -> Result<(), JsValue> {
+        rmp::encode::write_bin(&mut self.buf, &bytes.to_vec()).map_err(write_error)?;
+        Ok(())
+    }
+
+    fn entries(&mut self, entries: &[(JsValue, JsValue)]) -> Result<(), JsValue> {
+        self.enter()?;
+        rmp::encode::write_map_len(&mut self.buf, entries.len() as u32).map_err(write_error)?;
+        for (key, value) in entries {
+            self.value(key)?;
+            self.value(value)?;
+        }
+        self.depth -= 1;
+        Ok(())
+    }
+
+    fn date(&mut self, date: &Date) -> Result<(), JsValue> {
+        let timestamp = Timestamp::from_millis(date.get_time())
+            .ok_or_else(|| JsValue::from_str("Cannot encode an invalid Date"))?;
+        self.ext(TIMESTAMP_EXT_TYPE, &timestamp.to_ext_data())
+    }
+
+    fn ext(&mut self, ext_type: i8, data: &[u8]) -> Result<(), JsValue> {
+        rmp::encode::write_ext_meta(&mut self.buf, data.len() as u32, ext_type).map_err(write_error)?;
+        self.buf.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn enter(&mut self) -> Result<(), JsValue> {
+        if self.depth == MAX_DEPTH {
+            return Err(JsValue::from_str("Value nested too deep (or cyclic)"));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+}
+
+/// `{ extType, data }` as produced by the decoder for ext values.
+fn ext_parts(object: &Object) -> Result<Option<(i8, Uint8Array)>, JsValue> {
+    let ext_type = Reflect::get(object, &JsValue::from_str("extType"))?;
+    let data = Reflect::get(object, &JsValue::from_str("data"))?;
+    match (ext_type.as_f64(), data.dyn_into::<Uint8Array>()) {
+        (Some(t), Ok(data)) if t.fract() == 0.0 && (-128.0..=127.0).contains(&t) => Ok(Some((t as i8, data))),
+        _ => Ok(None),
+    }
+}
+
+fn write_error(e: impl std::fmt::Display) -> JsValue {
+    JsValue::from_str(&format!("MessagePack encoding error: {}", e))
+}
@@ -0,0 +1,246 @@
+use wasm_bindgen::prelude::*;
+use js_sys::{Array, Function, Object, Reflect, Uint8Array};
+use std::collections::BTreeMap;
+
+use crate::{decode, encode};
+
+/// Numeric field of a fixed ext layout, stored big-endian.
+#[derive(Clone, Copy)]
+enum FieldType {
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    F32,
+    F64,
+}
+
+impl FieldType {
+    fn parse(name: &str) -> Option<FieldType> {
+        match name {
+            "u8" => Some(FieldType::U8),
+            "i8" => Some(FieldType::I8),
+            "u16" => Some(FieldType::U16),
+            "i16" => Some(FieldType::I16),
+            "u32" => Some(FieldType::U32),
+            "i32" => Some(FieldType::I32),
+            "f32" => Some(FieldType::F32),
+            "f64" => Some(FieldType::F64),
+            _ => None,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            FieldType::U8 | FieldType::I8 => 1,
+            FieldType::U16 | FieldType::I16 => 2,
+            FieldType::U32 | FieldType::I32 | FieldType::F32 => 4,
+            FieldType::F64 => 8,
+        }
+    }
+
+    // Целые приводятся как в typed arrays: с усечением и переполнением
+    fn write(self, value: f64, out: &mut Vec<u8>) {
+        match self {
+            FieldType::U8 => out.push(value as i64 as u8),
+            FieldType::I8 => out.push(value as i64 as i8 as u8),
+            FieldType::U16 => out.extend_from_slice(&(value as i64 as u16).to_be_bytes()),
+            FieldType::I16 => out.extend_from_slice(&(value as i64 as i16).to_be_bytes()),
+            FieldType::U32 => out.extend_from_slice(&(value as i64 as u32).to_be_bytes()),
+            FieldType::I32 => out.extend_from_slice(&(value as i64 as i32).to_be_bytes()),
+            FieldType::F32 => out.extend_from_slice(&(value as f32).to_be_bytes()),
+            FieldType::F64 => out.extend_from_slice(&value.to_be_bytes()),
+        }
+    }
+
+    /// `bytes` is exactly `size()` long.
+    fn read(self, bytes: &[u8]) -> f64 {
+        match self {
+            FieldType::U8 => bytes[0] as f64,
+            FieldType::I8 => bytes[0] as i8 as f64,
+            FieldType::U16 => u16::from_be_bytes([bytes[0], bytes[1]]) as f64,
+            FieldType::I16 => i16::from_be_bytes([bytes[0], bytes[1]]) as f64,
+            FieldType::U32 => u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            FieldType::I32 => i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            FieldType::F32 => f32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            FieldType::F64 => f64::from_be_bytes(bytes.try_into().unwrap()),
+        }
+    }
+}
+
+enum Codec {
+    /// `pack(value) -> Uint8Array` and `unpack(Uint8Array) -> value`.
+    Callbacks { pack: Function, unpack: Function },
+    /// Named numeric fields packed back to back.
+    Layout(Vec<(JsValue, FieldType)>),
+}
+
+struct ExtType {
+    /// Values that are `instanceof` this class are written with this type.
+    class: Function,
+    codec: Codec,
+}
+
+/// Game-specific ext types, e.g. type 1 = `Vec2` packed as two f32.
+///
+/// ```js
+/// const registry = new ExtRegistry();
+/// registry.registerLayout(1, Vec2, ["x:f32", "y:f32"]);
+/// registry.register(2, Color, c => Uint8Array.of(c.r, c.g, c.b), b => new Color(b[0], b[1], b[2]));
+/// const bytes = registry.encode({ pos: new Vec2(1, 2) });
+/// ```
+///
+/// Layout types decode to objects with the class's prototype, without calling
+/// its constructor.
+#[wasm_bindgen]
+pub struct ExtRegistry {
+    // BTreeMap: при пересечении классов побеждает меньший код, а не случайный
+    types: BTreeMap<i8, ExtType>,
+}
+
+impl Default for ExtRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[wasm_bindgen]
+impl ExtRegistry {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> ExtRegistry {
+        ExtRegistry { types: BTreeMap::new() }
+    }
+
+    /// Register `type_code` (0..=127) with JS pack/unpack callbacks.
+    pub fn register(&mut self, type_code: i32, class: Function, pack: Function, unpack: Function) -> Result<(), JsValue> {
+        let type_code = check_type_code(type_code)?;
+        self.types.insert(type_code, ExtType { class, codec: Codec::Callbacks { pack, unpack } });
+        Ok(())
+    }
+
+    /// Register `type_code` (0..=127) with a fixed layout such as
+    /// `["x:f32", "y:f32"]`; field types are u8, i8, u16, i16, u32, i32, f32
+    /// and f64, all big-endian.
+    #[wasm_bindgen(js_name = registerLayout)]
+    pub fn register_layout(&mut self, type_code: i32, class: Function, fields: Array) -> Result<(), JsValue> {
+        let type_code = check_type_code(type_code)?;
+        let mut layout = Vec::with_capacity(fields.length() as usize);
+        for field in fields.iter() {
+            let spec = field.as_string().unwrap_or_default();
+            let parsed = spec
+                .split_once(':')
+                .and_then(|(name, ty)| Some((JsValue::from_str(name), FieldType::parse(ty)?)));
+            match parsed {
+                Some(entry) => layout.push(entry),
+                None => return Err(JsValue::from_str(&format!("Invalid layout field: {:?}", spec))),
+            }
+        }
+        self.types.insert(type_code, ExtType { class, codec: Codec::Layout(layout) });
+        Ok(())
+    }
+
+    pub fn unregister(&mut self, type_code: i32) -> bool {
+        i8::try_from(type_code).is_ok_and(|code| self.types.remove(&code).is_some())
+    }
+
+    /// Like the free `encode`, with registered classes written as their ext type.
+    pub fn encode(&self, value: &JsValue) -> Result<Uint8Array, JsValue> {
+        let mut encoder = encode::Encoder::new().with_registry(self);
+        encoder.value(value)?;
+        Ok(Uint8Array::from(encoder.into_bytes().as_slice()))
+    }
+
+    /// Like the free `decode`, with registered ext types unpacked.
+    pub fn decode(&self, data: &Uint8Array) -> Result<JsValue, JsValue> {
+        let buf = data.to_vec();
+        let mut decoder = decode::Decoder::new(&buf).with_registry(self);
+        let value = decoder.value()?;
+        decoder.finish()?;
+        Ok(value)
+    }
+}
+
+impl ExtRegistry {
+    /// Ext type and payload for `value` if its class is registered.
+    pub(crate) fn pack(&self, value: &JsValue) -> Result<Option<(i8, Vec<u8>)>, JsValue> {
+        if !value.is_object() {
+            return Ok(None);
+        }
+        for (&type_code, ext) in &self.types {
+            if !is_instance(value, &ext.class)? {
+                continue;
+            }
+            let data = match &ext.codec {
+                Codec::Callbacks { pack, .. } => {
+                    let packed = pack.call1(&JsValue::NULL, value)?;
+                    packed
+                        .dyn_into::<Uint8Array>()
+                        .map_err(|_| JsValue::from_str(&format!("Pack callback for ext type {} must return a Uint8Array", type_code)))?
+                        .to_vec()
+                }
+                Codec::Layout(fields) => {
+                    let mut data = Vec::new();
+                    for (name, field_type) in fields {
+                        let field = Reflect::get(value, name)?.as_f64().unwrap_or(f64::NAN);
+                        field_type.write(field, &mut data);
+                    }
+                    data
+                }
+            };
+            return Ok(Some((type_code, data)));
+        }
+        Ok(None)
+    }
+
+    /// Value for a registered ext type, `None` for unregistered ones.
+    pub(crate) fn unpack(&self, type_code: i8, data: &[u8]) -> Result<Option<JsValue>, JsValue> {
+        let Some(ext) = self.types.get(&type_code) else {
+            return Ok(None);
+        };
+        let value = match &ext.codec {
+            Codec::Callbacks { unpack, .. } => unpack.call1(&JsValue::NULL, &Uint8Array::from(data))?,
+            Codec::Layout(fields) => {
+                let expected: usize = fields.iter().map(|(_, field_type)| field_type.size()).sum();
+                if data.len() != expected {
+                    return Err(JsValue::from_str(&format!(
+                        "Ext type {} expects {} bytes, got {}", type_code, expected, data.len()
+                    )));
+                }
+                let prototype = Reflect::get(&ext.class, &JsValue::from_str("prototype"))?;
+                let object: Object = Object::create(&prototype.unchecked_into::<Object>());
+                let mut offset = 0;
+                for (name, field_type) in fields {
+                    let size = field_type.size();
+                    let field = field_type.read(&data[offset..offset + size]);
+                    Reflect::set(&object, name, &JsValue::from_f64(field))?;
+                    offset += size;
+                }
+                object.into()
+            }
+        };
+        Ok(Some(value))
+    }
+}
+
+/// `value instanceof class`, by walking the prototype chain.
+fn is_instance(value: &JsValue, class: &Function) -> Result<bool, JsValue> {
+    let prototype = Reflect::get(class, &JsValue::from_str("prototype"))?;
+    let mut current = Object::get_prototype_of(value);
+    while !current.is_null() {
+        if Object::is(&current, &prototype) {
+            return Ok(true);
+        }
+        current = Object::get_prototype_of(&current);
+    }
+    Ok(false)
+}
+
+/// Negative codes are reserved by the MessagePack spec.
+fn check_type_code(type_code: i32) -> Result<i8, JsValue> {
+    match i8::try_from(type_code) {
+        Ok(code) if code >= 0 => Ok(code),
+        _ => Err(JsValue::from_str(&format!("Ext type must be in 0..=127, got {}", type_code))),
+    }
+}
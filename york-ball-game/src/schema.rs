@@ -0,0 +1,651 @@
+//! Message schemas declared once from JS and used to pack/unpack the
+//! positional array messages by field name.
+//!
+//! A schema is a list of fields, each one of:
+//! - `"name:type"` – one value; `type` is u8, u16, u32, i8, i16, i32, f32,
+//!   f64, string or bool, optionally followed by `[N]` for a fixed tuple of N
+//!   values (a JS array) and/or `?` for a field that may be nil (`null`)
+//! - `{ name, list: "type" }` – a count followed by that many values
+//! - `{ name, list: [fields...] }` – a count followed by that many groups of
+//!   fields, as JS objects
+//!
+//! For example the server's `RoomList` is
+//! `[{ name: "rooms", list: ["id:u32", "name:string", "players:u32", "max_players:u32"] }]`.
+
+use wasm_bindgen::prelude::*;
+use js_sys::{Array, Object, Reflect, Uint8Array};
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use crate::decode::Decoder;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Scalar {
+    U8,
+    U16,
+    U32,
+    I8,
+    I16,
+    I32,
+    F32,
+    F64,
+    Str,
+    Bool,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Option<Scalar> {
+        match name {
+            "u8" => Some(Scalar::U8),
+            "u16" => Some(Scalar::U16),
+            "u32" => Some(Scalar::U32),
+            "i8" => Some(Scalar::I8),
+            "i16" => Some(Scalar::I16),
+            "i32" => Some(Scalar::I32),
+            "f32" => Some(Scalar::F32),
+            "f64" => Some(Scalar::F64),
+            "string" => Some(Scalar::Str),
+            "bool" => Some(Scalar::Bool),
+            _ => None,
+        }
+    }
+
+    fn int_range(self) -> Option<(f64, f64)> {
+        match self {
+            Scalar::U8 => Some((0.0, u8::MAX as f64)),
+            Scalar::U16 => Some((0.0, u16::MAX as f64)),
+            Scalar::U32 => Some((0.0, u32::MAX as f64)),
+            Scalar::I8 => Some((i8::MIN as f64, i8::MAX as f64)),
+            Scalar::I16 => Some((i16::MIN as f64, i16::MAX as f64)),
+            Scalar::I32 => Some((i32::MIN as f64, i32::MAX as f64)),
+            _ => None,
+        }
+    }
+
+    /// Whether a decoded or to-be-encoded JS value fits this type.
+    fn accepts(self, value: &JsValue) -> bool {
+        match self {
+            Scalar::Str => value.is_string(),
+            Scalar::Bool => value.as_bool().is_some(),
+            Scalar::F32 | Scalar::F64 => value.as_f64().is_some(),
+            _ => {
+                let (min, max) = self.int_range().unwrap();
+                value.as_f64().is_some_and(|n| n.fract() == 0.0 && (min..=max).contains(&n))
+            }
+        }
+    }
+
+    /// `value` must already pass `accepts`.
+    fn write(self, value: &JsValue, buf: &mut Vec<u8>) -> Result<(), JsValue> {
+        match self {
+            Scalar::Str => rmp::encode::write_str(buf, &value.as_string().unwrap_or_default()).map_err(write_error),
+            Scalar::Bool => rmp::encode::write_bool(buf, value.as_bool().unwrap_or_default()).map_err(write_error),
+            Scalar::F32 => rmp::encode::write_f32(buf, value.as_f64().unwrap_or_default() as f32).map_err(write_error),
+            Scalar::F64 => rmp::encode::write_f64(buf, value.as_f64().unwrap_or_default()).map_err(write_error),
+            _ => {
+                let n = value.as_f64().unwrap_or_default();
+                if n >= 0.0 {
+                    rmp::encode::write_uint(buf, n as u64).map(|_| ()).map_err(write_error)
+                } else {
+                    rmp::encode::write_sint(buf, n as i64).map(|_| ()).map_err(write_error)
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct ValueType {
+    scalar: Scalar,
+    /// `Some(n)` for a fixed tuple of `n` values.
+    tuple: Option<u32>,
+    optional: bool,
+}
+
+impl ValueType {
+    fn parse(spec: &str) -> Option<ValueType> {
+        let (spec, optional) = match spec.strip_suffix('?') {
+            Some(spec) => (spec, true),
+            None => (spec, false),
+        };
+        let (scalar, tuple) = match spec.strip_suffix(']').and_then(|spec| spec.split_once('[')) {
+            Some((scalar, len)) => (scalar, Some(len.parse().ok().filter(|len| *len > 0)?)),
+            None => (spec, None),
+        };
+        Some(ValueType { scalar: Scalar::parse(scalar)?, tuple, optional })
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Shape {
+    Value(ValueType),
+    List(ValueType),
+    Group(Vec<Field>),
+}
+
+#[derive(Clone, Debug)]
+struct Field {
+    name: String,
+    shape: Shape,
+}
+
+thread_local! {
+    static SCHEMAS: RefCell<HashMap<String, Vec<Field>>> = RefCell::new(HashMap::new());
+}
+
+/// Declare (or replace) the schema of message type `name`; see the module
+/// docs for the field syntax.
+#[wasm_bindgen]
+pub fn define_schema(name: &str, fields: Array) -> Result<(), JsValue> {
+    let fields = parse_fields(&fields)?;
+    SCHEMAS.with(|schemas| schemas.borrow_mut().insert(name.to_string(), fields));
+    Ok(())
+}
+
+/// Validate `obj` against the schema `schema_name` and pack it as
+/// `[schema_name, fields...]`.
+#[wasm_bindgen]
+pub fn encode_message(schema_name: &str, obj: &JsValue) -> Result<Uint8Array, JsValue> {
+    let fields = schema(schema_name)?;
+
+    let mut body = Vec::new();
+    let mut count = 0;
+    write_fields(&fields, obj, &mut body, &mut count)?;
+
+    let mut buf = Vec::with_capacity(body.len() + 8);
+    rmp::encode::write_array_len(&mut buf, 1 + count).map_err(write_error)?;
+    rmp::encode::write_str(&mut buf, schema_name).map_err(write_error)?;
+    buf.extend_from_slice(&body);
+    Ok(Uint8Array::from(buf.as_slice()))
+}
+
+/// Unpack an array message into `{ type, ...fields }` using the schema named
+/// by its first element.
+#[wasm_bindgen]
+pub fn decode_message(data: &Uint8Array) -> Result<JsValue, JsValue> {
+    let buf = data.to_vec();
+    let mut decoder = Decoder::new(&buf);
+    let mut remaining = decoder.array_len()?;
+    remaining = remaining.checked_sub(1).ok_or_else(|| JsValue::from_str("Empty message"))?;
+    let message_type = decoder.str()?;
+    let fields = schema(message_type)?;
+
+    let result = Object::new();
+    Reflect::set(&result, &"type".into(), &message_type.into())?;
+    read_fields(&fields, &result, &mut decoder, &mut remaining)?;
+    if remaining != 0 {
+        return Err(JsValue::from_str(&format!("{}: {} unexpected trailing fields", message_type, remaining)));
+    }
+    decoder.finish()?;
+    Ok(result.into())
+}
+
+fn schema(name: &str) -> Result<Vec<Field>, JsValue> {
+    SCHEMAS
+        .with(|schemas| schemas.borrow().get(name).cloned())
+        .ok_or_else(|| JsValue::from_str(&format!("No schema for message type {}", name)))
+}
+
+fn parse_fields(fields: &Array) -> Result<Vec<Field>, JsValue> {
+    let mut parsed = Vec::with_capacity(fields.length() as usize);
+    for field in fields.iter() {
+        let invalid = || JsValue::from_str(&format!("Invalid schema field: {:?}", field));
+
+        let parsed_field = if let Some(spec) = field.as_string() {
+            let (name, ty) = spec.split_once(':').ok_or_else(invalid)?;
+            Field { name: name.to_string(), shape: Shape::Value(ValueType::parse(ty).ok_or_else(invalid)?) }
+        } else {
+            let name = Reflect::get(&field, &"name".into())?.as_string().ok_or_else(invalid)?;
+            let list = Reflect::get(&field, &"list".into())?;
+            let shape = if let Some(ty) = list.as_string() {
+                Shape::List(ValueType::parse(&ty).ok_or_else(invalid)?)
+            } else if Array::is_array(&list) {
+                // Пустая группа не занимает места в массиве: count мог бы быть любым
+                let group = parse_fields(&Array::from(&list))?;
+                if group.is_empty() {
+                    return Err(invalid());
+                }
+                Shape::Group(group)
+            } else {
+                return Err(invalid());
+            };
+            Field { name, shape }
+        };
+        parsed.push(parsed_field);
+    }
+    Ok(parsed)
+}
+
+/// Appends the fields of `obj` to `buf`, adding the number of array
+/// elements written to `count`.
+fn write_fields(fields: &[Field], obj: &JsValue, buf: &mut Vec<u8>, count: &mut u32) -> Result<(), JsValue> {
+    if !obj.is_object() {
+        return Err(JsValue::from_str("Expected an object"));
+    }
+    for field in fields {
+        let value = Reflect::get(obj, &JsValue::from_str(&field.name))?;
+        match &field.shape {
+            Shape::Value(ty) => write_value(&field.name, *ty, &value, buf, count)?,
+            Shape::List(ty) => {
+                let items = list(&field.name, &value)?;
+                write_count(items.length(), buf, count)?;
+                for item in items.iter() {
+                    write_value(&field.name, *ty, &item, buf, count)?;
+                }
+            }
+            Shape::Group(group) => {
+                let items = list(&field.name, &value)?;
+                write_count(items.length(), buf, count)?;
+                for item in items.iter() {
+                    write_fields(group, &item, buf, count)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn write_value(name: &str, ty: ValueType, value: &JsValue, buf: &mut Vec<u8>, count: &mut u32) -> Result<(), JsValue> {
+    if ty.optional && (value.is_null() || value.is_undefined()) {
+        // кортеж остаётся той же длины: nil на каждый элемент
+        for _ in 0..ty.tuple.unwrap_or(1) {
+            rmp::encode::write_nil(buf).map_err(write_error)?;
+            *count += 1;
+        }
+        return Ok(());
+    }
+
+    let values = match ty.tuple {
+        Some(len) => {
+            let items = list(name, value)?;
+            if items.length() != len {
+                return Err(JsValue::from_str(&format!("Field {}: expected {} values, got {}", name, len, items.length())));
+            }
+            items.iter().collect()
+        }
+        None => vec![value.clone()],
+    };
+    for value in &values {
+        if !ty.scalar.accepts(value) {
+            return Err(type_error(name, ty.scalar, value));
+        }
+        ty.scalar.write(value, buf)?;
+        *count += 1;
+    }
+    Ok(())
+}
+
+fn write_count(len: u32, buf: &mut Vec<u8>, count: &mut u32) -> Result<(), JsValue> {
+    rmp::encode::write_uint(buf, len as u64).map_err(write_error)?;
+    *count += 1;
+    Ok(())
+}
+
+/// Reads the fields into `obj`; `remaining` counts the array elements left.
+fn read_fields(fields: &[Field], obj: &Object, decoder: &mut Decoder, remaining: &mut u32) -> Result<(), JsValue> {
+    for field in fields {
+        let value = match &field.shape {
+            Shape::Value(ty) => read_value(&field.name, *ty, decoder, remaining)?,
+            Shape::List(ty) => {
+                let items = Array::new();
+                for _ in 0..read_count(&field.name, decoder, remaining)? {
+                    items.push(&read_value(&field.name, *ty, decoder, remaining)?);
+                }
+                items.into()
+            }
+            Shape::Group(group) => {
+                let items = Array::new();
+                for _ in 0..read_count(&field.name, decoder, remaining)? {
+                    let item = Object::new();
+                    read_fields(group, &item, decoder, remaining)?;
+                    items.push(&item);
+                }
+                items.into()
+            }
+        };
+        Reflect::set(obj, &JsValue::from_str(&field.name), &value)?;
+    }
+    Ok(())
+}
+
+fn read_value(name: &str, ty: ValueType, decoder: &mut Decoder, remaining: &mut u32) -> Result<JsValue, JsValue> {
+    let len = ty.tuple.unwrap_or(1);
+    if *remaining < len {
+        return Err(JsValue::from_str(&format!("Missing field: {}", name)));
+    }
+    *remaining -= len;
+
+    let mut values = Vec::with_capacity(len as usize);
+    for _ in 0..len {
+        let value = decoder.value()?;
+        if !(ty.scalar.accepts(&value) || ty.optional && value.is_null()) {
+            return Err(type_error(name, ty.scalar, &value));
+        }
+        values.push(value);
+    }
+
+    if ty.optional && values.iter().all(JsValue::is_null) {
+        return Ok(JsValue::NULL);
+    }
+    Ok(match ty.tuple {
+        Some(_) => values.into_iter().collect::<Array>().into(),
+        None => values.pop().unwrap_or(JsValue::NULL),
+    })
+}
+
+/// Every list item takes at least one array element, so a count above the
+/// elements left is rejected before anything is allocated for it.
+fn read_count(name: &str, decoder: &mut Decoder, remaining: &mut u32) -> Result<u32, JsValue> {
+    let count = read_value(name, ValueType { scalar: Scalar::U32, tuple: None, optional: false }, decoder, remaining)?;
+    let count = count.as_f64().unwrap_or_default() as u32;
+    if count > *remaining {
+        return Err(JsValue::from_str(&format!("Field {}: {} items, but only {} fields left", name, count, remaining)));
+    }
+    Ok(count)
+}
+
+fn list(name: &str, value: &JsValue) -> Result<Array, JsValue> {
+    if Array::is_array(value) {
+        Ok(Array::from(value))
+    } else {
+        Err(JsValue::from_str(&format!("Field {}: expected an array", name)))
+    }
+}
+
+fn type_error(name: &str, expected: Scalar, value: &JsValue) -> JsValue {
+    JsValue::from_str(&format!("Field {}: expected {:?}, got {:?}", name, expected, value))
+}
+
+fn write_error(e: impl std::fmt::Display) -> JsValue {
+    JsValue::from_str(&format!("MessagePack encoding error: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use york_protocol::snapshot::{EntityState, PositionEncoding, Snapshot};
+    use york_protocol::{ClientMessage, MatchPhase, PlayerStats, RoomInfo, ServerMessage, Team};
+
+    fn ty(scalar: Scalar, tuple: Option<u32>, optional: bool) -> Option<ValueType> {
+        Some(ValueType { scalar, tuple, optional })
+    }
+
+    #[test]
+    fn every_scalar_type_parses() {
+        let scalars = [
+            ("u8", Scalar::U8),
+            ("u16", Scalar::U16),
+            ("u32", Scalar::U32),
+            ("i8", Scalar::I8),
+            ("i16", Scalar::I16),
+            ("i32", Scalar::I32),
+            ("f32", Scalar::F32),
+            ("f64", Scalar::F64),
+            ("string", Scalar::Str),
+            ("bool", Scalar::Bool),
+        ];
+        for (name, scalar) in scalars {
+            assert_eq!(ValueType::parse(name), ty(scalar, None, false));
+        }
+    }
+
+    #[test]
+    fn tuples_and_optional_values() {
+        assert_eq!(ValueType::parse("f64?"), ty(Scalar::F64, None, true));
+        assert_eq!(ValueType::parse("f32[2]"), ty(Scalar::F32, Some(2), false));
+        assert_eq!(ValueType::parse("u32[4]?"), ty(Scalar::U32, Some(4), true));
+    }
+
+    #[test]
+    fn malformed_types_are_rejected() {
+        for spec in ["", "u64", "U8", "string?[2]", "f32[]", "f32[0]", "f32[x]", "f32[-1]", "f32[2", "f32??", " u8"] {
+            assert_eq!(ValueType::parse(spec), None, "{:?}", spec);
+        }
+    }
+
+    #[test]
+    fn integer_ranges() {
+        assert_eq!(Scalar::U8.int_range(), Some((0.0, 255.0)));
+        assert_eq!(Scalar::I16.int_range(), Some((-32768.0, 32767.0)));
+        assert_eq!(Scalar::U32.int_range(), Some((0.0, 4294967295.0)));
+        assert_eq!(Scalar::F64.int_range(), None);
+        assert_eq!(Scalar::Str.int_range(), None);
+    }
+
+    /// `MESSAGE_SCHEMAS` of the JS client: the schemas it registers with
+    /// `define_schema` at startup.
+    fn client_schemas() -> HashMap<String, Vec<Field>> {
+        let source = include_str!("../../client/client_with_wasm.js");
+        let start = source.find("const MESSAGE_SCHEMAS = {").expect("MESSAGE_SCHEMAS not found");
+        let end = start + source[start..].find("\n};").expect("end of MESSAGE_SCHEMAS not found");
+        let body = source[start..end + 3].split_once('=').unwrap().1;
+
+        let mut tokens = JsTokens::new(body);
+        let mut schemas = HashMap::new();
+        tokens.expect("{");
+        while tokens.peek() != "}" {
+            let name = tokens.next();
+            tokens.expect(":");
+            schemas.insert(name, tokens.fields());
+            tokens.comma_unless("}");
+        }
+        schemas
+    }
+
+    /// Just enough of a JS object literal for `MESSAGE_SCHEMAS`.
+    struct JsTokens {
+        tokens: Vec<String>,
+        pos: usize,
+    }
+
+    impl JsTokens {
+        fn new(source: &str) -> Self {
+            let mut tokens = Vec::new();
+            let mut chars = source.chars().peekable();
+            while let Some(c) = chars.next() {
+                match c {
+                    '/' if chars.peek() == Some(&'/') => {
+                        chars.by_ref().find(|c| *c == '\n');
+                    }
+                    '"' => tokens.push(chars.by_ref().take_while(|c| *c != '"').collect()),
+                    '{' | '}' | '[' | ']' | ':' | ',' | ';' => tokens.push(c.to_string()),
+                    c if c.is_whitespace() => {}
+                    c => {
+                        let mut word = c.to_string();
+                        while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_') {
+                            word.push(c);
+                        }
+                        tokens.push(word);
+                    }
+                }
+            }
+            JsTokens { tokens, pos: 0 }
+        }
+
+        fn peek(&self) -> &str {
+            &self.tokens[self.pos]
+        }
+
+        fn next(&mut self) -> String {
+            self.pos += 1;
+            self.tokens[self.pos - 1].clone()
+        }
+
+        fn expect(&mut self, token: &str) {
+            assert_eq!(self.next(), token, "MESSAGE_SCHEMAS syntax");
+        }
+
+        fn comma_unless(&mut self, end: &str) {
+            if self.peek() != end {
+                self.expect(",");
+            }
+        }
+
+        fn fields(&mut self) -> Vec<Field> {
+            let mut fields = Vec::new();
+            self.expect("[");
+            while self.peek() != "]" {
+                if self.peek() == "{" {
+                    self.next();
+                    self.expect("name");
+                    self.expect(":");
+                    let name = self.next();
+                    self.expect(",");
+                    self.expect("list");
+                    self.expect(":");
+                    let shape = if self.peek() == "[" {
+                        Shape::Group(self.fields())
+                    } else {
+                        Shape::List(ValueType::parse(&self.next()).expect("list type"))
+                    };
+                    self.expect("}");
+                    fields.push(Field { name, shape });
+                } else {
+                    let spec = self.next();
+                    let (name, ty) = spec.split_once(':').expect("name:type");
+                    let ty = ValueType::parse(ty).unwrap_or_else(|| panic!("Invalid type in {}", spec));
+                    fields.push(Field { name: name.to_string(), shape: Shape::Value(ty) });
+                }
+                self.comma_unless("]");
+            }
+            self.next();
+            fields
+        }
+    }
+
+    /// Walks an encoded message like `decode_message` does, checking each
+    /// MessagePack value against the schema instead of building JS objects.
+    fn check_message(schemas: &HashMap<String, Vec<Field>>, mut data: &[u8]) -> String {
+        let buf = &mut data;
+        let mut remaining = rmp::decode::read_array_len(buf).unwrap() - 1;
+        let mut name = vec![0; 64];
+        let name = rmp::decode::read_str(buf, &mut name).unwrap().to_string();
+        let fields = schemas.get(&name).unwrap_or_else(|| panic!("No schema for {}", name));
+
+        check_fields(&name, fields, buf, &mut remaining);
+        assert_eq!(remaining, 0, "{}: trailing fields", name);
+        assert!(buf.is_empty(), "{}: trailing bytes", name);
+        name
+    }
+
+    fn check_fields(message: &str, fields: &[Field], buf: &mut &[u8], remaining: &mut u32) {
+        for field in fields {
+            let context = format!("{}.{}", message, field.name);
+            match &field.shape {
+                Shape::Value(ty) => check_value(&context, *ty, buf, remaining),
+                Shape::List(ty) => {
+                    for _ in 0..check_count(&context, buf, remaining) {
+                        check_value(&context, *ty, buf, remaining);
+                    }
+                }
+                Shape::Group(group) => {
+                    for _ in 0..check_count(&context, buf, remaining) {
+                        check_fields(message, group, buf, remaining);
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_count(context: &str, buf: &mut &[u8], remaining: &mut u32) -> u32 {
+        assert!(*remaining > 0, "{}: missing count", context);
+        *remaining -= 1;
+        rmp::decode::read_int(buf).unwrap_or_else(|e| panic!("{}: count expected: {:?}", context, e))
+    }
+
+    fn check_value(context: &str, ty: ValueType, buf: &mut &[u8], remaining: &mut u32) {
+        let len = ty.tuple.unwrap_or(1);
+        assert!(*remaining >= len, "{}: missing field", context);
+        *remaining -= len;
+
+        for _ in 0..len {
+            let marker = buf[0];
+            let fits = match marker {
+                0xc0 => {
+                    *buf = &buf[1..];
+                    ty.optional
+                }
+                0xc2 | 0xc3 => rmp::decode::read_bool(buf).is_ok() && matches!(ty.scalar, Scalar::Bool),
+                0xca => rmp::decode::read_f32(buf).is_ok() && matches!(ty.scalar, Scalar::F32 | Scalar::F64),
+                0xcb => rmp::decode::read_f64(buf).is_ok() && matches!(ty.scalar, Scalar::F32 | Scalar::F64),
+                0xa0..=0xbf | 0xd9..=0xdb => {
+                    let len = rmp::decode::read_str_len(buf).unwrap() as usize;
+                    *buf = &buf[len..];
+                    matches!(ty.scalar, Scalar::Str)
+                }
+                _ => {
+                    let n: i64 = rmp::decode::read_int(buf).unwrap_or_else(|e| panic!("{}: 0x{:02x}: {:?}", context, marker, e));
+                    match ty.scalar.int_range() {
+                        Some((min, max)) => (min..=max).contains(&(n as f64)),
+                        // JS не отличает целые от дробных
+                        None => matches!(ty.scalar, Scalar::F32 | Scalar::F64),
+                    }
+                }
+            };
+            assert!(fits, "{}: 0x{:02x} does not fit {:?}", context, marker, ty);
+        }
+    }
+
+    fn server_messages() -> Vec<ServerMessage> {
+        let entity = |x, y| EntityState { x, y, vel_x: -12.5, vel_y: 3.75 };
+        vec![
+            ServerMessage::Welcome { protocol_version: 3, client_id: 5, capabilities: vec!["batching".to_string(), "lz4".to_string()] },
+            ServerMessage::VersionMismatch { server_version: 3, client_version: 2 },
+            ServerMessage::Joined { id: 3 },
+            ServerMessage::Left { id: 4 },
+            ServerMessage::RoomLeft { room_id: 8 },
+            ServerMessage::Snapshot(Snapshot { tick: 42, ball: entity(400.0, 300.0), players: vec![(1, entity(100.0, 150.5)), (2, entity(700.25, 450.0))] }),
+            ServerMessage::Correction(entity(110.0, 100.0)),
+            ServerMessage::RoomJoined { room_id: 2, name: "arena".to_string(), max_players: 6 },
+            ServerMessage::RoomList { rooms: vec![RoomInfo { id: 1, name: "a".to_string(), players: 2, max_players: 4 }] },
+            ServerMessage::RoomError { reason: "room is full".to_string() },
+            ServerMessage::Warning { reason: "rate limit exceeded".to_string() },
+            ServerMessage::MatchFound { room_id: 3, team: Team::Right },
+            ServerMessage::GoalScored { team: Team::Left, scorer: Some(7), score: [1, 0] },
+            ServerMessage::GoalScored { team: Team::Right, scorer: None, score: [1, 1] },
+            ServerMessage::Kickoff { positions: vec![(1, 200.0, 300.0), (2, 600.0, 300.0)] },
+            ServerMessage::MatchState { phase: MatchPhase::Playing, left_side: Team::Right, score: [2, 3], teams: vec![(1, Team::Left), (2, Team::Right)] },
+            ServerMessage::Clock { half: 2, remaining_secs: 95 },
+            ServerMessage::MatchEnded {
+                score: [3, 1],
+                winner: Some(Team::Left),
+                stats: vec![(1, PlayerStats { team: Team::Left, goals: 3, kicks: 10, touches: 25 }), (2, PlayerStats::new(Team::Right))],
+            },
+            ServerMessage::MatchEnded { score: [0, 0], winner: None, stats: Vec::new() },
+        ]
+    }
+
+    fn client_messages() -> Vec<ClientMessage> {
+        vec![
+            ClientMessage::Hello { protocol_version: 3, client_build: "web".to_string(), capabilities: vec!["batching".to_string()] },
+            ClientMessage::Move { x: 100.0, y: 200.5, vel_x: 120.0, vel_y: 0.0 },
+            ClientMessage::Kick { x: 100.0, y: 200.5, dir_x: 0.6, dir_y: -0.8 },
+            ClientMessage::Ack { tick: 42 },
+            ClientMessage::CreateRoom { name: "arena".to_string(), max_players: 4 },
+            ClientMessage::JoinRoom { room_id: 2 },
+            ClientMessage::LeaveRoom {},
+            ClientMessage::ListRooms {},
+            ClientMessage::QueueForMatch {},
+            ClientMessage::StartMatch {},
+        ]
+    }
+
+    #[test]
+    fn client_schemas_match_the_protocol() {
+        let schemas = client_schemas();
+        let mut checked = std::collections::BTreeSet::new();
+
+        for msg in server_messages() {
+            for positions in [PositionEncoding::Float64, PositionEncoding::Float32, PositionEncoding::Quantized] {
+                checked.insert(check_message(&schemas, &msg.encode_with(positions).unwrap()));
+            }
+        }
+        for msg in client_messages() {
+            checked.insert(check_message(&schemas, &msg.encode().unwrap()));
+        }
+
+        // Delta разбирает SnapshotDecoder, схема для него не нужна
+        let declared: std::collections::BTreeSet<String> = schemas.into_keys().collect();
+        assert_eq!(checked, declared);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,221 @@
+use wasm_bindgen::prelude::*;
+use js_sys::{Array, Object, Reflect, Uint8Array};
+use std::collections::{BTreeMap, VecDeque};
+
+use york_protocol::snapshot::{EntityState, Snapshot, SnapshotDelta, BALL_ENTITY_ID};
+use york_protocol::ServerMessage;
+
+/// How many rebuilt states are kept as possible baselines for incoming deltas.
+const HISTORY_CAPACITY: usize = 32;
+
+/// Full world state at one tick, keyed by entity id (the ball uses `BALL_ENTITY_ID`).
+type WorldState = BTreeMap<u32, EntityState>;
+
+/// Rebuilds full world state from the server's `Snapshot` and `Delta` messages.
+///
+/// Every decoded state is remembered by tick so that later deltas can be
+/// applied on top of it; the caller acknowledges the returned `tick` back to
+/// the server, which then diffs against it.
+#[wasm_bindgen]
+pub struct SnapshotDecoder {
+    states: VecDeque<(u32, WorldState)>,
+}
+
+impl Default for SnapshotDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[wasm_bindgen]
+impl SnapshotDecoder {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> SnapshotDecoder {
+        SnapshotDecoder {
+            states: VecDeque::with_capacity(HISTORY_CAPACITY),
+        }
+    }
+
+    /// Decode a `Snapshot` or `Delta` message into
+    /// `{ type: "Snapshot", tick, ball: {x, y, vel_x, vel_y}, players: [{id, x, y, vel_x, vel_y}] }`.
+    pub fn decode(&mut self, data: &Uint8Array) -> Result<JsValue, JsValue> {
+        let (tick, state) = self.decode_state(data)?;
+        let result = state_to_js(tick, &state)?;
+        self.remember(tick, state);
+        Ok(result)
+    }
+
+    /// Fast path of `decode` that skips building JS objects: entity `i` is
+    /// written as `ids[i]` and `fields[4 * i..4 * i + 4]` = `x, y, vel_x, vel_y`,
+    /// in id order, so the ball (`BALL_ENTITY_ID`) comes last. Returns the
+    /// entity count; the snapshot's tick is then in `tick`.
+    pub fn decode_into(&mut self, data: &Uint8Array, ids: &mut [u32], fields: &mut [f64]) -> Result<u32, JsValue> {
+        self.decode_fields(data, ids, fields, |value| value)
+    }
+
+    /// `decode_into` for a `Float32Array`.
+    pub fn decode_into_f32(&mut self, data: &Uint8Array, ids: &mut [u32], fields: &mut [f32]) -> Result<u32, JsValue> {
+        self.decode_fields(data, ids, fields, |value| value as f32)
+    }
+
+    /// Tick of the most recently decoded snapshot, 0 before the first one.
+    #[wasm_bindgen(getter)]
+    pub fn tick(&self) -> u32 {
+        self.states.back().map_or(0, |(tick, _)| *tick)
+    }
+
+    /// Forget all stored baselines, e.g. after reconnecting.
+    pub fn reset(&mut self) {
+        self.states.clear();
+    }
+}
+
+impl SnapshotDecoder {
+    fn decode_state(&self, data: &Uint8Array) -> Result<(u32, WorldState), JsValue> {
+        let buf = data.to_vec();
+        let msg = ServerMessage::decode(&buf).map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+        match msg {
+            ServerMessage::Snapshot(snapshot) => Ok(world_state(snapshot)),
+            ServerMessage::Delta(delta) => self.apply_delta(&delta),
+            other => Err(JsValue::from_str(&format!("Not a snapshot message: {}", other.msg_type()))),
+        }
+    }
+
+    fn decode_fields<T>(&mut self, data: &Uint8Array, ids: &mut [u32], fields: &mut [T], convert: fn(f64) -> T) -> Result<u32, JsValue> {
+        let (tick, state) = self.decode_state(data)?;
+        // Снимок не запоминается: вызывающий его не увидит и не подтвердит
+        if ids.len() < state.len() || fields.len() < state.len() * 4 {
+            return Err(JsValue::from_str(&format!("Buffers too small for {} entities", state.len())));
+        }
+
+        for (i, (id, entity)) in state.iter().enumerate() {
+            ids[i] = *id;
+            let values = [entity.x, entity.y, entity.vel_x, entity.vel_y];
+            for (field, value) in fields[i * 4..i * 4 + 4].iter_mut().zip(values) {
+                *field = convert(value);
+            }
+        }
+
+        let count = state.len() as u32;
+        self.remember(tick, state);
+        Ok(count)
+    }
+
+    fn remember(&mut self, tick: u32, state: WorldState) {
+        if self.states.len() == HISTORY_CAPACITY {
+            self.states.pop_front();
+        }
+        self.states.push_back((tick, state));
+    }
+
+    /// The newest stored state of `tick`, if it is still in the history.
+    fn baseline(&self, tick: u32) -> Option<&WorldState> {
+        self.states
+            .iter()
+            .rev()
+            .find(|(stored_tick, _)| *stored_tick == tick)
+            .map(|(_, state)| state)
+    }
+
+    fn apply_delta(&self, delta: &SnapshotDelta) -> Result<(u32, WorldState), JsValue> {
+        let mut state = self
+            .baseline(delta.baseline_tick)
+            .cloned()
+            .ok_or_else(|| JsValue::from_str(&format!("Unknown baseline tick: {}", delta.baseline_tick)))?;
+
+        for id in &delta.removed {
+            state.remove(id);
+        }
+        for entity in &delta.changed {
+            state.entry(entity.id).or_default().apply_delta(entity);
+        }
+
+        Ok((delta.tick, state))
+    }
+}
+
+fn world_state(snapshot: Snapshot) -> (u32, WorldState) {
+    let mut state: WorldState = snapshot.players.into_iter().collect();
+    state.insert(BALL_ENTITY_ID, snapshot.ball);
+    (snapshot.tick, state)
+}
+
+fn entity_to_js(entity: &EntityState) -> Result<Object, JsValue> {
+    let obj = Object::new();
+    Reflect::set(&obj, &"x".into(), &entity.x.into())?;
+    Reflect::set(&obj, &"y".into(), &entity.y.into())?;
+    Reflect::set(&obj, &"vel_x".into(), &entity.vel_x.into())?;
+    Reflect::set(&obj, &"vel_y".into(), &entity.vel_y.into())?;
+    Ok(obj)
+}
+
+fn state_to_js(tick: u32, state: &WorldState) -> Result<JsValue, JsValue> {
+    let players = Array::new();
+    let mut ball = EntityState::default();
+
+    for (id, entity) in state {
+        if *id == BALL_ENTITY_ID {
+            ball = *entity;
+            continue;
+        }
+        let player = entity_to_js(entity)?;
+        Reflect::set(&player, &"id".into(), &(*id).into())?;
+        players.push(&player);
+    }
+
+    let result = Object::new();
+    Reflect::set(&result, &"type".into(), &"Snapshot".into())?;
+    Reflect::set(&result, &"tick".into(), &tick.into())?;
+    Reflect::set(&result, &"ball".into(), &entity_to_js(&ball)?.into())?;
+    Reflect::set(&result, &"players".into(), &players)?;
+    Ok(result.into())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entity(x: f64, y: f64) -> EntityState {
+        EntityState { x, y, vel_x: 0.0, vel_y: 0.0 }
+    }
+
+    fn snapshot(tick: u32, ball_x: f64, players: &[(u32, f64)]) -> Snapshot {
+        Snapshot {
+            tick,
+            ball: entity(ball_x, 300.0),
+            players: players.iter().map(|&(id, x)| (id, entity(x, 100.0))).collect(),
+        }
+    }
+
+    #[test]
+    fn delta_is_applied_on_top_of_its_baseline() {
+        let mut decoder = SnapshotDecoder::new();
+        let baseline = snapshot(10, 400.0, &[(1, 200.0), (2, 600.0)]);
+        let current = snapshot(11, 410.0, &[(2, 600.0), (3, 250.0)]);
+        let (tick, state) = world_state(baseline.clone());
+        decoder.remember(tick, state);
+
+        let (tick, state) = decoder.apply_delta(&SnapshotDelta::between(&baseline, &current)).unwrap();
+        assert_eq!(tick, 11);
+        assert_eq!(state, world_state(current).1);
+    }
+
+    #[test]
+    fn history_keeps_the_newest_states() {
+        let mut decoder = SnapshotDecoder::new();
+        assert_eq!(decoder.tick(), 0);
+        for tick in 1..=HISTORY_CAPACITY as u32 + 1 {
+            let (_, state) = world_state(snapshot(tick, tick as f64, &[]));
+            decoder.remember(tick, state);
+        }
+
+        assert_eq!(decoder.tick(), HISTORY_CAPACITY as u32 + 1);
+        assert!(decoder.baseline(1).is_none());
+        assert_eq!(decoder.baseline(2).unwrap()[&BALL_ENTITY_ID].x, 2.0);
+
+        decoder.reset();
+        assert!(decoder.baseline(HISTORY_CAPACITY as u32).is_none());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,173 @@
+use wasm_bindgen::prelude::*;
+use js_sys::{Array, Uint8Array};
+
+use crate::decode;
+
+/// Largest incomplete value kept between chunks.
+const MAX_BUFFERED: usize = 16 * 1024 * 1024;
+
+/// Decodes a byte stream that arrives in arbitrary chunks, e.g. several
+/// messages batched in one frame or a replay file read piece by piece.
+///
+/// ```js
+/// const stream = new StreamDecoder();
+/// for (const chunk of chunks) {
+///     for (const value of stream.push(chunk)) handle(value);
+/// }
+/// ```
+#[wasm_bindgen]
+pub struct StreamDecoder {
+    buf: Vec<u8>,
+}
+
+impl Default for StreamDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[wasm_bindgen]
+impl StreamDecoder {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> StreamDecoder {
+        StreamDecoder { buf: Vec::new() }
+    }
+
+    /// Append a chunk and return every top-level value completed by it, in
+    /// order; an incomplete trailing value waits for the next chunk.
+    ///
+    /// Malformed data can't be resynchronised, so on error the buffer is
+    /// dropped and decoding starts over with the next chunk.
+    pub fn push(&mut self, chunk: &Uint8Array) -> Result<Array, JsValue> {
+        let start = self.buf.len();
+        self.buf.resize(start + chunk.length() as usize, 0);
+        chunk.copy_to(&mut self.buf[start..]);
+        let lens = self.complete_values().map_err(|e| JsValue::from_str(&e))?;
+
+        let values = Array::new();
+        let mut pos = 0;
+        for len in lens {
+            let mut decoder = decode::Decoder::new(&self.buf[pos..pos + len]);
+            match decoder.value() {
+                Ok(value) => values.push(&value),
+                Err(e) => {
+                    self.buf.clear();
+                    return Err(e);
+                }
+            };
+            pos += len;
+        }
+        self.buf.drain(..pos);
+        Ok(values)
+    }
+
+    /// Bytes of an incomplete value still waiting for more data.
+    #[wasm_bindgen(getter)]
+    pub fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Drop any buffered partial value, e.g. after reconnecting.
+    pub fn reset(&mut self) {
+        self.buf.clear();
+    }
+}
+
+impl StreamDecoder {
+    /// Lengths of the complete values at the start of the buffer; the caller
+    /// drains them once decoded. On error the buffer is dropped.
+    fn complete_values(&mut self) -> Result<Vec<usize>, String> {
+        let mut lens = Vec::new();
+        let mut pos = 0;
+        loop {
+            match york_protocol::value_len(&self.buf[pos..]) {
+                Ok(Some(len)) => {
+                    lens.push(len);
+                    pos += len;
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    self.buf.clear();
+                    return Err(e.to_string());
+                }
+            }
+        }
+
+        if self.buf.len() - pos > MAX_BUFFERED {
+            self.buf.clear();
+            return Err("Incomplete value exceeds the stream buffer limit".to_string());
+        }
+        Ok(lens)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds a chunk and returns the complete values like `push` would see them.
+    fn feed(stream: &mut StreamDecoder, chunk: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+        let mut values = Vec::new();
+        let mut pos = 0;
+        stream.buf.extend_from_slice(chunk);
+        for len in stream.complete_values()? {
+            values.push(stream.buf[pos..pos + len].to_vec());
+            pos += len;
+        }
+        stream.buf.drain(..pos);
+        Ok(values)
+    }
+
+    fn message(name: &str, n: u32) -> Vec<u8> {
+        let mut buf = Vec::new();
+        rmp::encode::write_array_len(&mut buf, 2).unwrap();
+        rmp::encode::write_str(&mut buf, name).unwrap();
+        rmp::encode::write_u32(&mut buf, n).unwrap();
+        buf
+    }
+
+    #[test]
+    fn values_split_across_chunks_are_joined() {
+        let first = message("Joined", 1);
+        let second = message("Snapshot", 70_000);
+        let data = [first.clone(), second.clone()].concat();
+        let mut stream = StreamDecoder::new();
+
+        let cut = first.len() + 3;
+        assert_eq!(feed(&mut stream, &data[..2]).unwrap(), Vec::<Vec<u8>>::new());
+        assert_eq!(stream.buffered(), 2);
+        assert_eq!(feed(&mut stream, &data[2..cut]).unwrap(), [first]);
+        assert_eq!(stream.buffered(), 3);
+        assert_eq!(feed(&mut stream, &data[cut..]).unwrap(), [second]);
+        assert_eq!(stream.buffered(), 0);
+    }
+
+    #[test]
+    fn several_values_in_one_chunk() {
+        let messages: Vec<Vec<u8>> = (0..3).map(|i| message("Clock", i)).collect();
+        let mut stream = StreamDecoder::new();
+        assert_eq!(feed(&mut stream, &messages.concat()).unwrap(), messages);
+    }
+
+    #[test]
+    fn malformed_data_drops_the_buffer() {
+        let mut stream = StreamDecoder::new();
+        feed(&mut stream, &message("Joined", 1)[..3]).unwrap();
+        // 0xc1 не используется в MessagePack
+        assert!(feed(&mut stream, &[0xc1]).is_err() || feed(&mut stream, &[0xc1; 16]).is_err());
+        assert_eq!(stream.buffered(), 0);
+
+        let next = message("Joined", 2);
+        assert_eq!(feed(&mut stream, &next).unwrap(), [next]);
+    }
+
+    #[test]
+    fn oversized_incomplete_value_is_dropped() {
+        let mut stream = StreamDecoder::new();
+        let mut header = vec![0xc6];
+        header.extend_from_slice(&(MAX_BUFFERED as u32 + 16).to_be_bytes());
+        feed(&mut stream, &header).unwrap();
+        assert!(feed(&mut stream, &vec![0; MAX_BUFFERED]).is_err());
+        assert_eq!(stream.buffered(), 0);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,137 @@
+//! Batch frames: several encoded messages concatenated back to back in one
+//! WebSocket frame, with no envelope header. A frame holding a single message
+//! is a batch of one, so receivers can always split.
+
+use crate::ProtocolError;
+
+/// Size in bytes of the first complete MessagePack value in `buf`, or `None`
+/// if `buf` ends before it does. Only walks headers, so it is cheap to call
+/// again on a growing buffer.
+pub fn value_len(buf: &[u8]) -> Result<Option<usize>, ProtocolError> {
+    let mut pos = 0;
+    // Сколько значений ещё нужно пропустить (элементы контейнеров добавляются сюда)
+    let mut pending: u64 = 1;
+
+    while pending > 0 {
+        pending -= 1;
+        let Some(&marker) = buf.get(pos) else { return Ok(None) };
+        pos += 1;
+
+        // (байт длины в заголовке, фиксированный размер данных, дочерних значений на единицу длины)
+        let (len_bytes, fixed, children_per_len): (usize, usize, u64) = match marker {
+            // fixint, nil, false, true
+            0x00..=0x7f | 0xe0..=0xff | 0xc0 | 0xc2 | 0xc3 => (0, 0, 0),
+            // fixmap
+            0x80..=0x8f => {
+                pending += 2 * (marker & 0x0f) as u64;
+                continue;
+            }
+            // fixarray
+            0x90..=0x9f => {
+                pending += (marker & 0x0f) as u64;
+                continue;
+            }
+            // fixstr
+            0xa0..=0xbf => (0, (marker & 0x1f) as usize, 0),
+            // bin 8/16/32, str 8/16/32
+            0xc4 | 0xd9 => (1, 0, 0),
+            0xc5 | 0xda => (2, 0, 0),
+            0xc6 | 0xdb => (4, 0, 0),
+            // ext 8/16/32: длина не включает байт типа
+            0xc7 => (1, 1, 0),
+            0xc8 => (2, 1, 0),
+            0xc9 => (4, 1, 0),
+            // uint/int 8, 16, 32 (и float32), 64 (и float64)
+            0xcc | 0xd0 => (0, 1, 0),
+            0xcd | 0xd1 => (0, 2, 0),
+            0xca | 0xce | 0xd2 => (0, 4, 0),
+            0xcb | 0xcf | 0xd3 => (0, 8, 0),
+            // fixext 1/2/4/8/16
+            0xd4..=0xd8 => (0, 1 + (1 << (marker - 0xd4)), 0),
+            // array 16/32, map 16/32
+            0xdc => (2, 0, 1),
+            0xdd => (4, 0, 1),
+            0xde => (2, 0, 2),
+            0xdf => (4, 0, 2),
+            // 0xc1 зарезервирован спецификацией
+            _ => return Err(ProtocolError::Decode(format!("invalid marker 0x{:02x}", marker))),
+        };
+
+        let header_end = checked_add(pos, len_bytes)?;
+        let Some(header) = buf.get(pos..header_end) else { return Ok(None) };
+        pos = header_end;
+        let len = header.iter().fold(0usize, |n, &byte| (n << 8) | byte as usize);
+
+        if children_per_len > 0 {
+            pending += len as u64 * children_per_len;
+        } else {
+            // На wasm32 длина из заголовка 32-битного формата может переполнить usize
+            pos = checked_add(pos, checked_add(fixed, len)?)?;
+        }
+    }
+
+    Ok(if pos <= buf.len() { Some(pos) } else { None })
+}
+
+fn checked_add(a: usize, b: usize) -> Result<usize, ProtocolError> {
+    a.checked_add(b).ok_or_else(|| ProtocolError::Decode("value length overflows".to_string()))
+}
+
+/// Splits a batch frame into its messages, still encoded.
+pub fn split_batch(data: &[u8]) -> Result<Vec<&[u8]>, ProtocolError> {
+    let mut messages = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let len = value_len(rest)?.ok_or_else(|| ProtocolError::Decode("truncated message in batch".to_string()))?;
+        let (message, tail) = rest.split_at(len);
+        messages.push(message);
+        rest = tail;
+    }
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lengths_of_complete_and_truncated_values() {
+        let mut buf = Vec::new();
+        rmp::encode::write_array_len(&mut buf, 3).unwrap();
+        rmp::encode::write_str(&mut buf, "Move").unwrap();
+        rmp::encode::write_bin(&mut buf, &[1; 300]).unwrap();
+        rmp::encode::write_map_len(&mut buf, 1).unwrap();
+        rmp::encode::write_u32(&mut buf, 7).unwrap();
+        rmp::encode::write_ext_meta(&mut buf, 2, 5).unwrap();
+        buf.extend_from_slice(&[0, 0]);
+
+        assert_eq!(value_len(&buf), Ok(Some(buf.len())));
+        for end in 0..buf.len() {
+            assert_eq!(value_len(&buf[..end]), Ok(None), "prefix of {} bytes", end);
+        }
+    }
+
+    #[test]
+    fn huge_declared_length_is_incomplete_or_an_error() {
+        // bin32/str32/ext32 с длиной около u32::MAX: не хватает данных (64 бита) или ошибка (wasm32),
+        // но никогда не длина короче заголовка
+        for marker in [0xc6, 0xdb, 0xc9] {
+            let buf = [marker, 0xff, 0xff, 0xff, 0xff, 0x00];
+            assert!(!matches!(value_len(&buf), Ok(Some(_))), "marker 0x{:02x}", marker);
+        }
+        assert_eq!(checked_add(usize::MAX, 1), Err(ProtocolError::Decode("value length overflows".to_string())));
+    }
+
+    #[test]
+    fn split_batch_returns_each_message() {
+        let mut frame = crate::ClientMessage::Ack { tick: 1 }.encode().unwrap();
+        frame.extend(crate::ClientMessage::ListRooms {}.encode().unwrap());
+        let messages = split_batch(&frame).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(crate::message_type(messages[1]).unwrap(), "ListRooms");
+
+        frame.pop();
+        assert!(split_batch(&frame).is_err());
+        assert!(split_batch(&[0xc1]).is_err());
+    }
+}
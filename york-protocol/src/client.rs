@@ -0,0 +1,232 @@
+use serde::{Deserialize, Serialize};
+
+use crate::read::Reader;
+use crate::{ProtocolError, RoomId};
+
+/// Messages sent by the browser to the server.
+///
+/// The canonical encoding is the array form written by `encode`; the serde
+/// derive also reads the older map form `{"type": "Move", "x": ...}`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type")]
+pub enum ClientMessage {
+    /// Must be the first message on a connection.
+    #[serde(rename = "Hello")]
+    Hello {
+        protocol_version: u32,
+        client_build: String,
+        /// Names of the `Capability`s the client supports.
+        capabilities: Vec<String>
+    },
+
+    #[serde(rename = "Move")]
+    Move {
+        x: f64,
+        y: f64,
+        #[serde(rename = "vel_x")]
+        vel_x: f64,
+        #[serde(rename = "vel_y")]
+        vel_y: f64
+    },
+
+    #[serde(rename = "Kick")]
+    Kick {
+        x: f64,
+        y: f64,
+        #[serde(rename = "dirX")]
+        dir_x: f64,
+        #[serde(rename = "dirY")]
+        dir_y: f64
+    },
+
+    #[serde(rename = "Ack")]
+    Ack { tick: u32 },
+
+    #[serde(rename = "CreateRoom")]
+    CreateRoom { name: String, max_players: u32 },
+
+    #[serde(rename = "JoinRoom")]
+    JoinRoom { room_id: RoomId },
+
+    // Пустые struct-варианты, чтобы принимались и {"type": ...}, и ["LeaveRoom"]
+    #[serde(rename = "LeaveRoom")]
+    LeaveRoom {},
+
+    #[serde(rename = "ListRooms")]
+    ListRooms {},
+
+    #[serde(rename = "QueueForMatch")]
+    QueueForMatch {},
+
+    #[serde(rename = "StartMatch")]
+    StartMatch {},
+}
+
+impl ClientMessage {
+    pub fn msg_type(&self) -> &'static str {
+        match self {
+            ClientMessage::Hello { .. } => "Hello",
+            ClientMessage::Move { .. } => "Move",
+            ClientMessage::Kick { .. } => "Kick",
+            ClientMessage::Ack { .. } => "Ack",
+            ClientMessage::CreateRoom { .. } => "CreateRoom",
+            ClientMessage::JoinRoom { .. } => "JoinRoom",
+            ClientMessage::LeaveRoom {} => "LeaveRoom",
+            ClientMessage::ListRooms {} => "ListRooms",
+            ClientMessage::QueueForMatch {} => "QueueForMatch",
+            ClientMessage::StartMatch {} => "StartMatch",
+        }
+    }
+
+    /// Writes the array form, e.g. `["Move", x, y, vel_x, vel_y]`; `Hello` is
+    /// `["Hello", protocol_version, client_build, count, capabilities...]`.
+    pub fn encode(&self) -> Result<Vec<u8>, ProtocolError> {
+        let mut buf = Vec::new();
+
+        let field_count = match self {
+            ClientMessage::Hello { capabilities, .. } => 3 + capabilities.len() as u32,
+            ClientMessage::Move { .. } | ClientMessage::Kick { .. } => 4,
+            ClientMessage::CreateRoom { .. } => 2,
+            ClientMessage::Ack { .. } | ClientMessage::JoinRoom { .. } => 1,
+            _ => 0,
+        };
+        rmp::encode::write_array_len(&mut buf, 1 + field_count)?;
+        rmp::encode::write_str(&mut buf, self.msg_type())?;
+
+        match self {
+            ClientMessage::Hello { protocol_version, client_build, capabilities } => {
+                rmp::encode::write_uint(&mut buf, *protocol_version as u64)?;
+                rmp::encode::write_str(&mut buf, client_build)?;
+                rmp::encode::write_uint(&mut buf, capabilities.len() as u64)?;
+                for capability in capabilities {
+                    rmp::encode::write_str(&mut buf, capability)?;
+                }
+            }
+            ClientMessage::Move { x, y, vel_x: a, vel_y: b }
+            | ClientMessage::Kick { x, y, dir_x: a, dir_y: b } => {
+                for value in [x, y, a, b] {
+                    rmp::encode::write_f64(&mut buf, *value)?;
+                }
+            }
+            ClientMessage::Ack { tick } => {
+                rmp::encode::write_uint(&mut buf, *tick as u64)?;
+            }
+            ClientMessage::CreateRoom { name, max_players } => {
+                rmp::encode::write_str(&mut buf, name)?;
+                rmp::encode::write_uint(&mut buf, *max_players as u64)?;
+            }
+            ClientMessage::JoinRoom { room_id } => {
+                rmp::encode::write_uint(&mut buf, *room_id as u64)?;
+            }
+            ClientMessage::LeaveRoom {}
+            | ClientMessage::ListRooms {}
+            | ClientMessage::QueueForMatch {}
+            | ClientMessage::StartMatch {} => {}
+        }
+
+        Ok(buf)
+    }
+
+    /// Reads the array form written by `encode`.
+    pub fn decode(data: &[u8]) -> Result<Self, ProtocolError> {
+        let mut r = Reader::new(data);
+        r.array_len()?;
+
+        let msg = match r.str("type")? {
+            "Hello" => {
+                let protocol_version = r.u32("protocol_version")?;
+                let client_build = r.str("client_build")?.to_string();
+                let count = r.count("capability_count", 1)?;
+                let mut capabilities = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    capabilities.push(r.str("capability")?.to_string());
+                }
+                ClientMessage::Hello { protocol_version, client_build, capabilities }
+            }
+            "Move" => ClientMessage::Move {
+                x: r.f64("x")?,
+                y: r.f64("y")?,
+                vel_x: r.f64("vel_x")?,
+                vel_y: r.f64("vel_y")?,
+            },
+            "Kick" => ClientMessage::Kick {
+                x: r.f64("x")?,
+                y: r.f64("y")?,
+                dir_x: r.f64("dirX")?,
+                dir_y: r.f64("dirY")?,
+            },
+            "Ack" => ClientMessage::Ack { tick: r.u32("tick")? },
+            "CreateRoom" => ClientMessage::CreateRoom {
+                name: r.str("name")?.to_string(),
+                max_players: r.u32("max_players")?,
+            },
+            "JoinRoom" => ClientMessage::JoinRoom { room_id: r.u32("room_id")? },
+            "LeaveRoom" => ClientMessage::LeaveRoom {},
+            "ListRooms" => ClientMessage::ListRooms {},
+            "QueueForMatch" => ClientMessage::QueueForMatch {},
+            "StartMatch" => ClientMessage::StartMatch {},
+            other => return Err(ProtocolError::UnknownType(other.to_string())),
+        };
+
+        r.finish()?;
+        Ok(msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all_messages() -> Vec<ClientMessage> {
+        vec![
+            ClientMessage::Hello {
+                protocol_version: crate::PROTOCOL_VERSION,
+                client_build: "web-1.2".to_string(),
+                capabilities: vec!["batching".to_string(), "lz4".to_string()],
+            },
+            ClientMessage::Hello { protocol_version: 7, client_build: String::new(), capabilities: Vec::new() },
+            ClientMessage::Move { x: 120.5, y: -3.25, vel_x: 0.0, vel_y: 99.75 },
+            ClientMessage::Kick { x: 400.0, y: 300.0, dir_x: 0.6, dir_y: -0.8 },
+            ClientMessage::Ack { tick: 123_456 },
+            ClientMessage::CreateRoom { name: "Комната".to_string(), max_players: 4 },
+            ClientMessage::JoinRoom { room_id: 9 },
+            ClientMessage::LeaveRoom {},
+            ClientMessage::ListRooms {},
+            ClientMessage::QueueForMatch {},
+            ClientMessage::StartMatch {},
+        ]
+    }
+
+    #[test]
+    fn every_message_round_trips() {
+        for msg in all_messages() {
+            let buf = msg.encode().unwrap();
+            assert_eq!(ClientMessage::decode(&buf).unwrap(), msg);
+            assert_eq!(crate::message_type(&buf).unwrap(), msg.msg_type());
+        }
+    }
+
+    #[test]
+    fn array_length_matches_written_fields() {
+        for msg in all_messages() {
+            let buf = msg.encode().unwrap();
+            assert_eq!(crate::value_len(&buf).unwrap(), Some(buf.len()), "{}", msg.msg_type());
+        }
+    }
+
+    #[test]
+    fn short_and_long_arrays_are_rejected() {
+        let mut buf = Vec::new();
+        rmp::encode::write_array_len(&mut buf, 2).unwrap();
+        rmp::encode::write_str(&mut buf, "Move").unwrap();
+        rmp::encode::write_f64(&mut buf, 1.0).unwrap();
+        assert_eq!(ClientMessage::decode(&buf), Err(ProtocolError::MissingField("y")));
+
+        let mut buf = Vec::new();
+        rmp::encode::write_array_len(&mut buf, 3).unwrap();
+        rmp::encode::write_str(&mut buf, "Ack").unwrap();
+        rmp::encode::write_uint(&mut buf, 1).unwrap();
+        rmp::encode::write_uint(&mut buf, 2).unwrap();
+        assert_eq!(ClientMessage::decode(&buf), Err(ProtocolError::TrailingFields(1)));
+    }
+}
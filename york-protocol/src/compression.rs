@@ -0,0 +1,128 @@
+//! LZ4 envelope for large server frames: `["Lz4", uncompressed_len, bin]`
+//! where `bin` is an LZ4 block holding the original frame (one message or a
+//! batch). Only sent to clients that agreed on `Capability::Lz4Frames`.
+
+use crate::read::Reader;
+use crate::ProtocolError;
+
+/// Type name of the envelope.
+pub const LZ4_FRAME_TYPE: &str = "Lz4";
+
+/// Smaller frames are sent as they are: LZ4 gains little on them.
+pub const COMPRESSION_THRESHOLD: usize = 256;
+
+/// Largest frame an envelope may expand to, so a forged length cannot make
+/// the receiver allocate without bound.
+pub const MAX_DECOMPRESSED_LEN: usize = 16 * 1024 * 1024;
+
+/// The envelope for `frame`, or `None` if it is below
+/// `COMPRESSION_THRESHOLD` or would not get smaller.
+pub fn compress_frame(frame: &[u8]) -> Result<Option<Vec<u8>>, ProtocolError> {
+    if frame.len() < COMPRESSION_THRESHOLD || frame.len() > MAX_DECOMPRESSED_LEN {
+        return Ok(None);
+    }
+    let compressed = lz4_flex::block::compress(frame);
+
+    let mut buf = Vec::with_capacity(compressed.len() + 16);
+    rmp::encode::write_array_len(&mut buf, 3)?;
+    rmp::encode::write_str(&mut buf, LZ4_FRAME_TYPE)?;
+    rmp::encode::write_uint(&mut buf, frame.len() as u64)?;
+    rmp::encode::write_bin(&mut buf, &compressed)?;
+    Ok((buf.len() < frame.len()).then_some(buf))
+}
+
+/// The original frame if `data` is an envelope, `None` for any other frame.
+pub fn decompress_frame(data: &[u8]) -> Result<Option<Vec<u8>>, ProtocolError> {
+    if crate::message_type(data).ok() != Some(LZ4_FRAME_TYPE) {
+        return Ok(None);
+    }
+
+    let mut r = Reader::new(data);
+    r.array_len()?;
+    r.str("type")?;
+    let len = r.u32("uncompressed_len")? as usize;
+    if len > MAX_DECOMPRESSED_LEN {
+        return Err(ProtocolError::InvalidValue("uncompressed_len"));
+    }
+    let compressed = r.bin("data")?;
+    r.finish()?;
+
+    let frame = lz4_flex::block::decompress(compressed, len).map_err(|e| ProtocolError::Decode(e.to_string()))?;
+    if frame.len() != len {
+        return Err(ProtocolError::InvalidValue("uncompressed_len"));
+    }
+    Ok(Some(frame))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn envelope(len: u64, compressed: &[u8]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        rmp::encode::write_array_len(&mut buf, 3).unwrap();
+        rmp::encode::write_str(&mut buf, LZ4_FRAME_TYPE).unwrap();
+        rmp::encode::write_uint(&mut buf, len).unwrap();
+        rmp::encode::write_bin(&mut buf, compressed).unwrap();
+        buf
+    }
+
+    fn room_list(rooms: u32) -> Vec<u8> {
+        let rooms = (0..rooms)
+            .map(|id| crate::RoomInfo { id, name: format!("room {}", id), players: 2, max_players: 4 })
+            .collect();
+        crate::ServerMessage::RoomList { rooms }.encode().unwrap()
+    }
+
+    #[test]
+    fn large_frame_round_trips() {
+        let frame = room_list(50);
+        assert!(frame.len() >= COMPRESSION_THRESHOLD);
+
+        let compressed = compress_frame(&frame).unwrap().unwrap();
+        assert!(compressed.len() < frame.len());
+        assert_eq!(crate::message_type(&compressed).unwrap(), LZ4_FRAME_TYPE);
+        assert_eq!(decompress_frame(&compressed).unwrap(), Some(frame));
+    }
+
+    #[test]
+    fn small_or_incompressible_frames_are_sent_as_they_are() {
+        let small = room_list(1);
+        assert!(small.len() < COMPRESSION_THRESHOLD);
+        assert_eq!(compress_frame(&small).unwrap(), None);
+
+        // Псевдослучайные байты LZ4 не сжимает
+        let mut seed = 1u32;
+        let noise: Vec<u8> = (0..1024)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (seed >> 16) as u8
+            })
+            .collect();
+        assert_eq!(compress_frame(&noise).unwrap(), None);
+    }
+
+    #[test]
+    fn other_frames_pass_through() {
+        assert_eq!(decompress_frame(&room_list(50)).unwrap(), None);
+        assert_eq!(decompress_frame(&[]).unwrap(), None);
+        assert_eq!(decompress_frame(&[0xc1, 0x00]).unwrap(), None);
+    }
+
+    #[test]
+    fn forged_length_is_rejected_before_allocating() {
+        let forged = envelope(MAX_DECOMPRESSED_LEN as u64 + 1, &[0x10, 0x00]);
+        assert_eq!(decompress_frame(&forged), Err(ProtocolError::InvalidValue("uncompressed_len")));
+    }
+
+    #[test]
+    fn length_must_match_the_payload() {
+        let frame = room_list(50);
+        let compressed = lz4_flex::block::compress(&frame);
+        assert_eq!(decompress_frame(&envelope(frame.len() as u64, &compressed)).unwrap(), Some(frame.clone()));
+
+        assert!(decompress_frame(&envelope(frame.len() as u64 + 1, &compressed)).is_err());
+        assert!(decompress_frame(&envelope(frame.len() as u64 - 1, &compressed)).is_err());
+        assert!(decompress_frame(&envelope(frame.len() as u64, &compressed[..compressed.len() - 1])).is_err());
+    }
+}
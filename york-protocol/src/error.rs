@@ -0,0 +1,42 @@
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+    /// Malformed MessagePack or a value of the wrong MessagePack type.
+    Decode(String),
+    Encode(String),
+    UnknownType(String),
+    /// The array ended before this field.
+    MissingField(&'static str),
+    /// The array has this many elements past the last field of its type.
+    TrailingFields(u32),
+    /// The field holds a value outside its allowed range.
+    InvalidValue(&'static str),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::Decode(e) => write!(f, "failed to decode message: {}", e),
+            ProtocolError::Encode(e) => write!(f, "failed to encode message: {}", e),
+            ProtocolError::UnknownType(msg_type) => write!(f, "unknown message type: {}", msg_type),
+            ProtocolError::MissingField(field) => write!(f, "missing field: {}", field),
+            ProtocolError::TrailingFields(count) => write!(f, "{} unexpected trailing fields", count),
+            ProtocolError::InvalidValue(field) => write!(f, "invalid value for field: {}", field),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+impl From<rmp::encode::ValueWriteError> for ProtocolError {
+    fn from(e: rmp::encode::ValueWriteError) -> Self {
+        ProtocolError::Encode(e.to_string())
+    }
+}
+
+impl From<std::io::Error> for ProtocolError {
+    fn from(e: std::io::Error) -> Self {
+        ProtocolError::Encode(e.to_string())
+    }
+}
@@ -0,0 +1,144 @@
+//! Wire format shared by the game server (`york-server`) and the browser
+//! codec (`msgpack_wasm`).
+//!
+//! Every message is a flat MessagePack array whose first element is the
+//! message type name, e.g. `["Move", x, y, vel_x, vel_y]`. Each type here has
+//! exactly one array encoding, so both sides always agree on field order.
+
+mod batch;
+mod client;
+mod compression;
+mod error;
+mod read;
+mod server;
+pub mod snapshot;
+mod timestamp;
+
+pub use batch::{split_batch, value_len};
+pub use client::ClientMessage;
+pub use compression::{compress_frame, decompress_frame, COMPRESSION_THRESHOLD, LZ4_FRAME_TYPE, MAX_DECOMPRESSED_LEN};
+pub use error::ProtocolError;
+pub use server::{RoomInfo, ServerMessage};
+pub use timestamp::{Timestamp, TIMESTAMP_EXT_TYPE};
+
+pub type ClientId = u32;
+pub type RoomId = u32;
+
+/// Version of this wire format. The server only accepts a `Hello` carrying
+/// exactly this version; bump it on every incompatible change.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Optional protocol features a client can ask for in `Hello`. The server
+/// answers with the ones it agrees to in `Welcome`; unknown names are ignored
+/// so that newer clients can still talk to older servers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Capability {
+    /// `Delta` snapshots against the client's last `Ack` instead of a full
+    /// `Snapshot` every tick.
+    DeltaSnapshots,
+    /// Server messages arrive as batch frames, see `split_batch`.
+    Batching,
+    /// `Snapshot` entities as float32, see `snapshot::PositionEncoding`.
+    Float32Positions,
+    /// `Snapshot` positions as i16 fixed-point; takes precedence over
+    /// `Float32Positions` when both are agreed.
+    QuantizedPositions,
+    /// Large server frames may arrive LZ4-compressed, see `decompress_frame`.
+    Lz4Frames,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 5] = [
+        Capability::DeltaSnapshots,
+        Capability::Batching,
+        Capability::Float32Positions,
+        Capability::QuantizedPositions,
+        Capability::Lz4Frames,
+    ];
+
+    /// Wire representation in `Hello` and `Welcome`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Capability::DeltaSnapshots => "delta_snapshots",
+            Capability::Batching => "batching",
+            Capability::Float32Positions => "f32_positions",
+            Capability::QuantizedPositions => "quantized_positions",
+            Capability::Lz4Frames => "lz4",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Capability> {
+        Capability::ALL.into_iter().find(|capability| capability.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Team {
+    Left,
+    Right,
+}
+
+impl Team {
+    /// Wire representation: 0 for `Left`, 1 for `Right`.
+    pub fn index(self) -> u32 {
+        match self {
+            Team::Left => 0,
+            Team::Right => 1,
+        }
+    }
+
+    pub fn from_index(index: u32) -> Option<Team> {
+        match index {
+            0 => Some(Team::Left),
+            1 => Some(Team::Right),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchPhase {
+    /// Free play between matches: no clock and no scoring.
+    Lobby,
+    Playing,
+}
+
+impl MatchPhase {
+    /// Wire representation: 0 for `Lobby`, 1 for `Playing`.
+    pub fn index(self) -> u32 {
+        match self {
+            MatchPhase::Lobby => 0,
+            MatchPhase::Playing => 1,
+        }
+    }
+
+    pub fn from_index(index: u32) -> Option<MatchPhase> {
+        match index {
+            0 => Some(MatchPhase::Lobby),
+            1 => Some(MatchPhase::Playing),
+            _ => None,
+        }
+    }
+}
+
+/// One player's line in the `MatchEnded` results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlayerStats {
+    pub team: Team,
+    pub goals: u32,
+    pub kicks: u32,
+    pub touches: u32,
+}
+
+impl PlayerStats {
+    pub fn new(team: Team) -> Self {
+        PlayerStats { team, goals: 0, kicks: 0, touches: 0 }
+    }
+}
+
+/// Type name of an encoded message, read without decoding the rest of it.
+pub fn message_type(data: &[u8]) -> Result<&str, ProtocolError> {
+    let mut reader = read::Reader::new(data);
+    reader.array_len()?;
+    reader.str("type")
+}
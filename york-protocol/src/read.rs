@@ -0,0 +1,131 @@
+use crate::snapshot::dequantize_coord;
+use crate::ProtocolError;
+
+const MSGPACK_NIL: u8 = 0xc0;
+const MSGPACK_FLOAT32: u8 = 0xca;
+const MSGPACK_FLOAT64: u8 = 0xcb;
+
+fn decode_error(e: impl std::fmt::Display) -> ProtocolError {
+    ProtocolError::Decode(e.to_string())
+}
+
+/// Reads the elements of one array message in order, keeping count of how
+/// many are left so that a short array reports the first missing field.
+pub(crate) struct Reader<'a> {
+    buf: &'a [u8],
+    remaining: u32,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Reader { buf, remaining: 0 }
+    }
+
+    pub fn array_len(&mut self) -> Result<u32, ProtocolError> {
+        let len = rmp::decode::read_array_len(&mut self.buf).map_err(decode_error)?;
+        self.remaining = len;
+        Ok(len)
+    }
+
+    /// Fails if the array has elements left over.
+    pub fn finish(&self) -> Result<(), ProtocolError> {
+        match self.remaining {
+            0 => Ok(()),
+            extra => Err(ProtocolError::TrailingFields(extra)),
+        }
+    }
+
+    fn next(&mut self, field: &'static str) -> Result<(), ProtocolError> {
+        if self.remaining == 0 {
+            return Err(ProtocolError::MissingField(field));
+        }
+        self.remaining -= 1;
+        Ok(())
+    }
+
+    fn peek(&self) -> Result<u8, ProtocolError> {
+        self.buf
+            .first()
+            .copied()
+            .ok_or_else(|| ProtocolError::Decode("unexpected end of data".into()))
+    }
+
+    pub fn str(&mut self, field: &'static str) -> Result<&'a str, ProtocolError> {
+        self.next(field)?;
+        let (s, rest) = rmp::decode::read_str_from_slice(self.buf).map_err(decode_error)?;
+        self.buf = rest;
+        Ok(s)
+    }
+
+    pub fn bin(&mut self, field: &'static str) -> Result<&'a [u8], ProtocolError> {
+        self.next(field)?;
+        let len = rmp::decode::read_bin_len(&mut self.buf).map_err(decode_error)? as usize;
+        if self.buf.len() < len {
+            return Err(ProtocolError::Decode("bin data out of bounds".into()));
+        }
+        let (data, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Ok(data)
+    }
+
+    pub fn u32(&mut self, field: &'static str) -> Result<u32, ProtocolError> {
+        self.next(field)?;
+        rmp::decode::read_int(&mut self.buf).map_err(decode_error)
+    }
+
+    pub fn i32(&mut self, field: &'static str) -> Result<i32, ProtocolError> {
+        self.next(field)?;
+        rmp::decode::read_int(&mut self.buf).map_err(decode_error)
+    }
+
+    /// `nil` or an unsigned integer.
+    pub fn opt_u32(&mut self, field: &'static str) -> Result<Option<u32>, ProtocolError> {
+        if self.remaining > 0 && self.peek()? == MSGPACK_NIL {
+            self.next(field)?;
+            rmp::decode::read_nil(&mut self.buf).map_err(decode_error)?;
+            return Ok(None);
+        }
+        self.u32(field).map(Some)
+    }
+
+    // Числа могут прийти как float32, float64 или целые
+    pub fn f64(&mut self, field: &'static str) -> Result<f64, ProtocolError> {
+        self.next(field)?;
+        match self.peek()? {
+            MSGPACK_FLOAT32 => rmp::decode::read_f32(&mut self.buf)
+                .map(|n| n as f64)
+                .map_err(decode_error),
+            MSGPACK_FLOAT64 => rmp::decode::read_f64(&mut self.buf).map_err(decode_error),
+            _ => rmp::decode::read_int::<i64, _>(&mut self.buf)
+                .map(|n| n as f64)
+                .map_err(decode_error),
+        }
+    }
+
+    /// Coordinate written with any `PositionEncoding`: floats as they are,
+    /// integers as i16 fixed-point relative to `size`.
+    pub fn coord(&mut self, field: &'static str, size: f64) -> Result<f64, ProtocolError> {
+        self.next(field)?;
+        match self.peek()? {
+            MSGPACK_FLOAT32 => rmp::decode::read_f32(&mut self.buf)
+                .map(|n| n as f64)
+                .map_err(decode_error),
+            MSGPACK_FLOAT64 => rmp::decode::read_f64(&mut self.buf).map_err(decode_error),
+            _ => {
+                let value = rmp::decode::read_int(&mut self.buf).map_err(|_| ProtocolError::InvalidValue(field))?;
+                Ok(dequantize_coord(value, size))
+            }
+        }
+    }
+
+    /// Element count for a repeated group of `group_len` fields, checked
+    /// against what is left in the array so a bogus count cannot make the
+    /// caller allocate more than the message holds.
+    pub fn count(&mut self, field: &'static str, group_len: u32) -> Result<u32, ProtocolError> {
+        let count = self.u32(field)?;
+        if count.saturating_mul(group_len) > self.remaining {
+            return Err(ProtocolError::InvalidValue(field));
+        }
+        Ok(count)
+    }
+}
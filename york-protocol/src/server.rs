@@ -0,0 +1,568 @@
+use crate::read::Reader;
+use crate::snapshot::{
+    quantize_coord, EntityDelta, EntityState, PositionEncoding, Snapshot, SnapshotDelta, ALL_FIELDS, FIELD_HEIGHT,
+    FIELD_WIDTH,
+};
+use crate::{ClientId, MatchPhase, PlayerStats, ProtocolError, RoomId, Team};
+
+/// Short description of a room for `RoomList`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoomInfo {
+    pub id: RoomId,
+    pub name: String,
+    pub players: u32,
+    pub max_players: u32,
+}
+
+/// Messages sent by the server to the browser.
+///
+/// Layouts are flat arrays; repeated groups are preceded by their count.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerMessage {
+    /// `["Welcome", protocol_version, client_id, count, capabilities...]` -
+    /// answer to `Hello` with the connection's own client id and the agreed
+    /// capabilities.
+    Welcome { protocol_version: u32, client_id: ClientId, capabilities: Vec<String> },
+    /// `["VersionMismatch", server_version, client_version]` - answer to a
+    /// `Hello` with an unsupported version; the server closes the connection.
+    VersionMismatch { server_version: u32, client_version: u32 },
+    /// `["Joined", id]`
+    Joined { id: ClientId },
+    /// `["Left", id]`
+    Left { id: ClientId },
+    /// `["RoomLeft", room_id]`
+    RoomLeft { room_id: RoomId },
+    /// `["Snapshot", tick, ball_x, ball_y, ball_vel_x, ball_vel_y, count, (id, x, y, vel_x, vel_y)...]`
+    /// with numbers in the client's `PositionEncoding`.
+    Snapshot(Snapshot),
+    /// `["Correction", x, y, vel_x, vel_y]` - authoritative state of the
+    /// receiving client's own player after its `Move` was rejected or clamped.
+    Correction(EntityState),
+    /// `["Delta", tick, baseline_tick, removed_count, removed_ids..., changed_count, (id, mask, values...)...]`
+    /// where only the quantized values selected by `mask` are present.
+    Delta(SnapshotDelta),
+    /// `["RoomJoined", room_id, name, max_players]`
+    RoomJoined { room_id: RoomId, name: String, max_players: u32 },
+    /// `["RoomList", count, (id, name, players, max_players)...]`
+    RoomList { rooms: Vec<RoomInfo> },
+    /// `["RoomError", reason]`
+    RoomError { reason: String },
+    /// `["Warning", reason]` - the client is misbehaving (e.g. sending too
+    /// fast) and will be disconnected if it goes on.
+    Warning { reason: String },
+    /// `["MatchFound", room_id, team]`
+    MatchFound { room_id: RoomId, team: Team },
+    /// `["GoalScored", team, scorer_id | nil, score_left, score_right]`
+    GoalScored { team: Team, scorer: Option<ClientId>, score: [u32; 2] },
+    /// `["Kickoff", count, (id, x, y)...]`
+    Kickoff { positions: Vec<(ClientId, f64, f64)> },
+    /// `["MatchState", phase, left_side, score_left, score_right, count, (id, team)...]`
+    MatchState { phase: MatchPhase, left_side: Team, score: [u32; 2], teams: Vec<(ClientId, Team)> },
+    /// `["Clock", half, remaining_secs]`
+    Clock { half: u32, remaining_secs: u32 },
+    /// `["MatchEnded", score_left, score_right, winner | nil, count, (id, team, goals, kicks, touches)...]`
+    MatchEnded { score: [u32; 2], winner: Option<Team>, stats: Vec<(ClientId, PlayerStats)> },
+}
+
+impl ServerMessage {
+    pub fn msg_type(&self) -> &'static str {
+        match self {
+            ServerMessage::Welcome { .. } => "Welcome",
+            ServerMessage::VersionMismatch { .. } => "VersionMismatch",
+            ServerMessage::Joined { .. } => "Joined",
+            ServerMessage::Left { .. } => "Left",
+            ServerMessage::RoomLeft { .. } => "RoomLeft",
+            ServerMessage::Snapshot(_) => "Snapshot",
+            ServerMessage::Correction(_) => "Correction",
+            ServerMessage::Delta(_) => "Delta",
+            ServerMessage::RoomJoined { .. } => "RoomJoined",
+            ServerMessage::RoomList { .. } => "RoomList",
+            ServerMessage::RoomError { .. } => "RoomError",
+            ServerMessage::Warning { .. } => "Warning",
+            ServerMessage::MatchFound { .. } => "MatchFound",
+            ServerMessage::GoalScored { .. } => "GoalScored",
+            ServerMessage::Kickoff { .. } => "Kickoff",
+            ServerMessage::MatchState { .. } => "MatchState",
+            ServerMessage::Clock { .. } => "Clock",
+            ServerMessage::MatchEnded { .. } => "MatchEnded",
+        }
+    }
+
+    /// Number of array elements after the type name.
+    fn field_count(&self) -> usize {
+        match self {
+            ServerMessage::Welcome { capabilities, .. } => 3 + capabilities.len(),
+            ServerMessage::Joined { .. }
+            | ServerMessage::Left { .. }
+            | ServerMessage::RoomLeft { .. }
+            | ServerMessage::RoomError { .. }
+            | ServerMessage::Warning { .. } => 1,
+            ServerMessage::Snapshot(snapshot) => 6 + 5 * snapshot.players.len(),
+            ServerMessage::Correction(_) => 4,
+            ServerMessage::Delta(delta) => {
+                let changed: usize = delta.changed.iter().map(|entity| 2 + entity.mask.count_ones() as usize).sum();
+                4 + delta.removed.len() + changed
+            }
+            ServerMessage::RoomJoined { .. } => 3,
+            ServerMessage::RoomList { rooms } => 1 + 4 * rooms.len(),
+            ServerMessage::VersionMismatch { .. } | ServerMessage::MatchFound { .. } | ServerMessage::Clock { .. } => 2,
+            ServerMessage::GoalScored { .. } => 4,
+            ServerMessage::Kickoff { positions } => 1 + 3 * positions.len(),
+            ServerMessage::MatchState { teams, .. } => 5 + 2 * teams.len(),
+            ServerMessage::MatchEnded { stats, .. } => 4 + 5 * stats.len(),
+        }
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, ProtocolError> {
+        self.encode_with(PositionEncoding::Float64)
+    }
+
+    /// Like `encode`, with `Snapshot` entities in the given form.
+    pub fn encode_with(&self, positions: PositionEncoding) -> Result<Vec<u8>, ProtocolError> {
+        let mut buf = Vec::new();
+
+        rmp::encode::write_array_len(&mut buf, 1 + self.field_count() as u32)?;
+        rmp::encode::write_str(&mut buf, self.msg_type())?;
+
+        match self {
+            ServerMessage::Welcome { protocol_version, client_id, capabilities } => {
+                rmp::encode::write_u32(&mut buf, *protocol_version)?;
+                rmp::encode::write_u32(&mut buf, *client_id)?;
+                rmp::encode::write_u32(&mut buf, capabilities.len() as u32)?;
+                for capability in capabilities {
+                    rmp::encode::write_str(&mut buf, capability)?;
+                }
+            }
+            ServerMessage::VersionMismatch { server_version, client_version } => {
+                rmp::encode::write_u32(&mut buf, *server_version)?;
+                rmp::encode::write_u32(&mut buf, *client_version)?;
+            }
+            ServerMessage::Joined { id } | ServerMessage::Left { id } => {
+                rmp::encode::write_u32(&mut buf, *id)?;
+            }
+            ServerMessage::RoomLeft { room_id } => {
+                rmp::encode::write_u32(&mut buf, *room_id)?;
+            }
+            ServerMessage::Snapshot(snapshot) => {
+                rmp::encode::write_u32(&mut buf, snapshot.tick)?;
+                write_entity(&mut buf, &snapshot.ball, positions)?;
+                rmp::encode::write_u32(&mut buf, snapshot.players.len() as u32)?;
+                for (id, player) in &snapshot.players {
+                    rmp::encode::write_u32(&mut buf, *id)?;
+                    write_entity(&mut buf, player, positions)?;
+                }
+            }
+            ServerMessage::Correction(state) => {
+                write_entity(&mut buf, state, PositionEncoding::Float64)?;
+            }
+            ServerMessage::Delta(delta) => {
+                // Дельты идут каждый тик, поэтому целые пишутся в самой короткой форме
+                rmp::encode::write_uint(&mut buf, delta.tick as u64)?;
+                rmp::encode::write_uint(&mut buf, delta.baseline_tick as u64)?;
+                rmp::encode::write_uint(&mut buf, delta.removed.len() as u64)?;
+                for id in &delta.removed {
+                    rmp::encode::write_uint(&mut buf, *id as u64)?;
+                }
+                rmp::encode::write_uint(&mut buf, delta.changed.len() as u64)?;
+                for entity in &delta.changed {
+                    rmp::encode::write_uint(&mut buf, entity.id as u64)?;
+                    rmp::encode::write_uint(&mut buf, entity.mask as u64)?;
+                    for value in entity.masked_values() {
+                        rmp::encode::write_sint(&mut buf, value as i64)?;
+                    }
+                }
+            }
+            ServerMessage::RoomJoined { room_id, name, max_players } => {
+                rmp::encode::write_u32(&mut buf, *room_id)?;
+                rmp::encode::write_str(&mut buf, name)?;
+                rmp::encode::write_u32(&mut buf, *max_players)?;
+            }
+            ServerMessage::RoomList { rooms } => {
+                rmp::encode::write_u32(&mut buf, rooms.len() as u32)?;
+                for room in rooms {
+                    rmp::encode::write_u32(&mut buf, room.id)?;
+                    rmp::encode::write_str(&mut buf, &room.name)?;
+                    rmp::encode::write_u32(&mut buf, room.players)?;
+                    rmp::encode::write_u32(&mut buf, room.max_players)?;
+                }
+            }
+            ServerMessage::RoomError { reason } | ServerMessage::Warning { reason } => {
+                rmp::encode::write_str(&mut buf, reason)?;
+            }
+            ServerMessage::MatchFound { room_id, team } => {
+                rmp::encode::write_u32(&mut buf, *room_id)?;
+                rmp::encode::write_u32(&mut buf, team.index())?;
+            }
+            ServerMessage::GoalScored { team, scorer, score } => {
+                rmp::encode::write_u32(&mut buf, team.index())?;
+                write_opt_u32(&mut buf, *scorer)?;
+                rmp::encode::write_u32(&mut buf, score[0])?;
+                rmp::encode::write_u32(&mut buf, score[1])?;
+            }
+            ServerMessage::Kickoff { positions } => {
+                rmp::encode::write_u32(&mut buf, positions.len() as u32)?;
+                for (id, x, y) in positions {
+                    rmp::encode::write_u32(&mut buf, *id)?;
+                    rmp::encode::write_f64(&mut buf, *x)?;
+                    rmp::encode::write_f64(&mut buf, *y)?;
+                }
+            }
+            ServerMessage::MatchState { phase, left_side, score, teams } => {
+                rmp::encode::write_u32(&mut buf, phase.index())?;
+                rmp::encode::write_u32(&mut buf, left_side.index())?;
+                rmp::encode::write_u32(&mut buf, score[0])?;
+                rmp::encode::write_u32(&mut buf, score[1])?;
+                rmp::encode::write_u32(&mut buf, teams.len() as u32)?;
+                for (id, team) in teams {
+                    rmp::encode::write_u32(&mut buf, *id)?;
+                    rmp::encode::write_u32(&mut buf, team.index())?;
+                }
+            }
+            ServerMessage::Clock { half, remaining_secs } => {
+                rmp::encode::write_u32(&mut buf, *half)?;
+                rmp::encode::write_u32(&mut buf, *remaining_secs)?;
+            }
+            ServerMessage::MatchEnded { score, winner, stats } => {
+                rmp::encode::write_u32(&mut buf, score[0])?;
+                rmp::encode::write_u32(&mut buf, score[1])?;
+                write_opt_u32(&mut buf, winner.map(Team::index))?;
+                rmp::encode::write_u32(&mut buf, stats.len() as u32)?;
+                for (id, player) in stats {
+                    rmp::encode::write_u32(&mut buf, *id)?;
+                    rmp::encode::write_u32(&mut buf, player.team.index())?;
+                    rmp::encode::write_u32(&mut buf, player.goals)?;
+                    rmp::encode::write_u32(&mut buf, player.kicks)?;
+                    rmp::encode::write_u32(&mut buf, player.touches)?;
+                }
+            }
+        }
+
+        Ok(buf)
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, ProtocolError> {
+        let mut r = Reader::new(data);
+        r.array_len()?;
+
+        let msg = match r.str("type")? {
+            "Welcome" => {
+                let protocol_version = r.u32("protocol_version")?;
+                let client_id = r.u32("client_id")?;
+                let count = r.count("capability_count", 1)?;
+                let mut capabilities = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    capabilities.push(r.str("capability")?.to_string());
+                }
+                ServerMessage::Welcome { protocol_version, client_id, capabilities }
+            }
+            "VersionMismatch" => ServerMessage::VersionMismatch {
+                server_version: r.u32("server_version")?,
+                client_version: r.u32("client_version")?,
+            },
+            "Joined" => ServerMessage::Joined { id: r.u32("id")? },
+            "Left" => ServerMessage::Left { id: r.u32("id")? },
+            "RoomLeft" => ServerMessage::RoomLeft { room_id: r.u32("room_id")? },
+            "Snapshot" => {
+                let tick = r.u32("tick")?;
+                let ball = read_entity(&mut r)?;
+                let count = r.count("player_count", 5)?;
+                let mut players = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    players.push((r.u32("id")?, read_entity(&mut r)?));
+                }
+                ServerMessage::Snapshot(Snapshot { tick, ball, players })
+            }
+            "Correction" => ServerMessage::Correction(read_entity(&mut r)?),
+            "Delta" => {
+                let tick = r.u32("tick")?;
+                let baseline_tick = r.u32("baseline_tick")?;
+                let removed_count = r.count("removed_count", 1)?;
+                let mut removed = Vec::with_capacity(removed_count as usize);
+                for _ in 0..removed_count {
+                    removed.push(r.u32("removed_id")?);
+                }
+                let changed_count = r.count("changed_count", 2)?;
+                let mut changed = Vec::with_capacity(changed_count as usize);
+                for _ in 0..changed_count {
+                    let id = r.u32("id")?;
+                    let mask = r.u32("mask")?;
+                    if mask & !(ALL_FIELDS as u32) != 0 {
+                        return Err(ProtocolError::InvalidValue("mask"));
+                    }
+                    let mut values = [0; 4];
+                    for (bit, value) in values.iter_mut().enumerate() {
+                        if mask & (1 << bit) != 0 {
+                            *value = r.i32("value")?;
+                        }
+                    }
+                    changed.push(EntityDelta { id, mask: mask as u8, values });
+                }
+                ServerMessage::Delta(SnapshotDelta { tick, baseline_tick, removed, changed })
+            }
+            "RoomJoined" => ServerMessage::RoomJoined {
+                room_id: r.u32("room_id")?,
+                name: r.str("name")?.to_string(),
+                max_players: r.u32("max_players")?,
+            },
+            "RoomList" => {
+                let count = r.count("count", 4)?;
+                let mut rooms = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    rooms.push(RoomInfo {
+                        id: r.u32("id")?,
+                        name: r.str("name")?.to_string(),
+                        players: r.u32("players")?,
+                        max_players: r.u32("max_players")?,
+                    });
+                }
+                ServerMessage::RoomList { rooms }
+            }
+            "RoomError" => ServerMessage::RoomError { reason: r.str("reason")?.to_string() },
+            "Warning" => ServerMessage::Warning { reason: r.str("reason")?.to_string() },
+            "MatchFound" => ServerMessage::MatchFound {
+                room_id: r.u32("room_id")?,
+                team: read_team(&mut r, "team")?,
+            },
+            "GoalScored" => ServerMessage::GoalScored {
+                team: read_team(&mut r, "team")?,
+                scorer: r.opt_u32("scorer_id")?,
+                score: [r.u32("score_left")?, r.u32("score_right")?],
+            },
+            "Kickoff" => {
+                let count = r.count("count", 3)?;
+                let mut positions = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    positions.push((r.u32("id")?, r.f64("x")?, r.f64("y")?));
+                }
+                ServerMessage::Kickoff { positions }
+            }
+            "MatchState" => {
+                let phase = MatchPhase::from_index(r.u32("phase")?).ok_or(ProtocolError::InvalidValue("phase"))?;
+                let left_side = read_team(&mut r, "left_side")?;
+                let score = [r.u32("score_left")?, r.u32("score_right")?];
+                let count = r.count("count", 2)?;
+                let mut teams = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    teams.push((r.u32("id")?, read_team(&mut r, "team")?));
+                }
+                ServerMessage::MatchState { phase, left_side, score, teams }
+            }
+            "Clock" => ServerMessage::Clock {
+                half: r.u32("half")?,
+                remaining_secs: r.u32("remaining_secs")?,
+            },
+            "MatchEnded" => {
+                let score = [r.u32("score_left")?, r.u32("score_right")?];
+                let winner = match r.opt_u32("winner")? {
+                    Some(index) => Some(Team::from_index(index).ok_or(ProtocolError::InvalidValue("winner"))?),
+                    None => None,
+                };
+                let count = r.count("count", 5)?;
+                let mut stats = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    let id = r.u32("id")?;
+                    stats.push((id, PlayerStats {
+                        team: read_team(&mut r, "team")?,
+                        goals: r.u32("goals")?,
+                        kicks: r.u32("kicks")?,
+                        touches: r.u32("touches")?,
+                    }));
+                }
+                ServerMessage::MatchEnded { score, winner, stats }
+            }
+            other => return Err(ProtocolError::UnknownType(other.to_string())),
+        };
+
+        r.finish()?;
+        Ok(msg)
+    }
+}
+
+fn write_entity(buf: &mut Vec<u8>, entity: &EntityState, positions: PositionEncoding) -> Result<(), ProtocolError> {
+    match positions {
+        PositionEncoding::Float64 => {
+            for value in [entity.x, entity.y, entity.vel_x, entity.vel_y] {
+                rmp::encode::write_f64(buf, value)?;
+            }
+        }
+        PositionEncoding::Float32 => {
+            for value in [entity.x, entity.y, entity.vel_x, entity.vel_y] {
+                rmp::encode::write_f32(buf, value as f32)?;
+            }
+        }
+        PositionEncoding::Quantized => {
+            rmp::encode::write_sint(buf, quantize_coord(entity.x, FIELD_WIDTH) as i64)?;
+            rmp::encode::write_sint(buf, quantize_coord(entity.y, FIELD_HEIGHT) as i64)?;
+            rmp::encode::write_f32(buf, entity.vel_x as f32)?;
+            rmp::encode::write_f32(buf, entity.vel_y as f32)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_opt_u32(buf: &mut Vec<u8>, value: Option<u32>) -> Result<(), ProtocolError> {
+    match value {
+        Some(value) => rmp::encode::write_u32(buf, value)?,
+        None => rmp::encode::write_nil(buf)?,
+    }
+    Ok(())
+}
+
+fn read_entity(r: &mut Reader<'_>) -> Result<EntityState, ProtocolError> {
+    Ok(EntityState {
+        x: r.coord("x", FIELD_WIDTH)?,
+        y: r.coord("y", FIELD_HEIGHT)?,
+        vel_x: r.f64("vel_x")?,
+        vel_y: r.f64("vel_y")?,
+    })
+}
+
+fn read_team(r: &mut Reader<'_>, field: &'static str) -> Result<Team, ProtocolError> {
+    Team::from_index(r.u32(field)?).ok_or(ProtocolError::InvalidValue(field))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entity(x: f64, y: f64, vel_x: f64, vel_y: f64) -> EntityState {
+        EntityState { x, y, vel_x, vel_y }
+    }
+
+    fn snapshot() -> Snapshot {
+        Snapshot {
+            tick: 42,
+            ball: entity(400.0, 300.0, -12.5, 3.75),
+            players: vec![(1, entity(100.0, 150.5, 120.0, 0.0)), (2, entity(700.25, 450.0, 0.0, -60.0))],
+        }
+    }
+
+    fn all_messages() -> Vec<ServerMessage> {
+        let moved = Snapshot {
+            tick: 43,
+            ball: entity(401.0, 300.0, -12.5, 3.75),
+            players: vec![(2, entity(700.25, 451.0, 0.0, -60.0)), (3, entity(50.0, 50.0, 1.0, 1.0))],
+        };
+        vec![
+            ServerMessage::Welcome {
+                protocol_version: crate::PROTOCOL_VERSION,
+                client_id: 5,
+                capabilities: vec!["delta_snapshots".to_string(), "batching".to_string()],
+            },
+            ServerMessage::Welcome { protocol_version: 1, client_id: 0, capabilities: Vec::new() },
+            ServerMessage::VersionMismatch { server_version: 1, client_version: 2 },
+            ServerMessage::Joined { id: 3 },
+            ServerMessage::Left { id: 4 },
+            ServerMessage::RoomLeft { room_id: 8 },
+            ServerMessage::Snapshot(snapshot()),
+            ServerMessage::Snapshot(Snapshot { tick: 0, ball: EntityState::default(), players: Vec::new() }),
+            ServerMessage::Correction(entity(110.0, 100.0, 120.0, 0.0)),
+            ServerMessage::Delta(SnapshotDelta::between(&snapshot(), &moved)),
+            ServerMessage::Delta(SnapshotDelta { tick: 5, baseline_tick: 4, removed: Vec::new(), changed: Vec::new() }),
+            ServerMessage::RoomJoined { room_id: 2, name: "arena".to_string(), max_players: 6 },
+            ServerMessage::RoomList {
+                rooms: vec![
+                    RoomInfo { id: 1, name: "a".to_string(), players: 2, max_players: 4 },
+                    RoomInfo { id: 2, name: "b".to_string(), players: 0, max_players: 2 },
+                ],
+            },
+            ServerMessage::RoomList { rooms: Vec::new() },
+            ServerMessage::RoomError { reason: "room is full".to_string() },
+            ServerMessage::Warning { reason: "rate limit exceeded".to_string() },
+            ServerMessage::MatchFound { room_id: 3, team: Team::Right },
+            ServerMessage::GoalScored { team: Team::Left, scorer: Some(7), score: [1, 0] },
+            ServerMessage::GoalScored { team: Team::Right, scorer: None, score: [1, 1] },
+            ServerMessage::Kickoff { positions: vec![(1, 200.0, 300.0), (2, 600.0, 300.0)] },
+            ServerMessage::MatchState {
+                phase: MatchPhase::Playing,
+                left_side: Team::Right,
+                score: [2, 3],
+                teams: vec![(1, Team::Left), (2, Team::Right)],
+            },
+            ServerMessage::MatchState { phase: MatchPhase::Lobby, left_side: Team::Left, score: [0, 0], teams: Vec::new() },
+            ServerMessage::Clock { half: 2, remaining_secs: 95 },
+            ServerMessage::MatchEnded {
+                score: [3, 1],
+                winner: Some(Team::Left),
+                stats: vec![
+                    (1, PlayerStats { team: Team::Left, goals: 3, kicks: 10, touches: 25 }),
+                    (2, PlayerStats::new(Team::Right)),
+                ],
+            },
+            ServerMessage::MatchEnded { score: [0, 0], winner: None, stats: Vec::new() },
+        ]
+    }
+
+    #[test]
+    fn every_message_round_trips() {
+        for msg in all_messages() {
+            let buf = msg.encode().unwrap();
+            assert_eq!(ServerMessage::decode(&buf).unwrap(), msg);
+            assert_eq!(crate::message_type(&buf).unwrap(), msg.msg_type());
+        }
+    }
+
+    #[test]
+    fn field_count_matches_written_fields() {
+        for msg in all_messages() {
+            for positions in [PositionEncoding::Float64, PositionEncoding::Float32, PositionEncoding::Quantized] {
+                let buf = msg.encode_with(positions).unwrap();
+                assert_eq!(crate::value_len(&buf).unwrap(), Some(buf.len()), "{} {:?}", msg.msg_type(), positions);
+
+                let mut r = Reader::new(&buf);
+                assert_eq!(r.array_len().unwrap() as usize, 1 + msg.field_count(), "{}", msg.msg_type());
+            }
+        }
+    }
+
+    #[test]
+    fn compact_snapshots_decode_close_to_original() {
+        let original = snapshot();
+        for (positions, tolerance) in [(PositionEncoding::Float32, 0.0), (PositionEncoding::Quantized, 0.05)] {
+            let buf = ServerMessage::Snapshot(original.clone()).encode_with(positions).unwrap();
+            let Ok(ServerMessage::Snapshot(decoded)) = ServerMessage::decode(&buf) else {
+                panic!("{:?}: not a snapshot", positions);
+            };
+            assert_eq!(decoded.tick, original.tick);
+            let pairs = std::iter::once((&decoded.ball, &original.ball))
+                .chain(decoded.players.iter().zip(&original.players).map(|((_, a), (_, b))| (a, b)));
+            for (a, b) in pairs {
+                assert!((a.x - b.x).abs() <= tolerance && (a.y - b.y).abs() <= tolerance, "{:?}: {:?} vs {:?}", positions, a, b);
+                assert_eq!((a.vel_x, a.vel_y), (b.vel_x, b.vel_y));
+            }
+        }
+    }
+
+    #[test]
+    fn delta_applies_to_baseline() {
+        let baseline = snapshot();
+        let mut current = baseline.clone();
+        current.tick += 1;
+        current.ball.x += 3.5;
+        current.players.remove(0);
+
+        let delta = SnapshotDelta::between(&baseline, &current);
+        assert_eq!(delta.removed, vec![1]);
+        assert_eq!(delta.changed.len(), 1);
+        assert_eq!(delta.changed[0].mask, crate::snapshot::FIELD_X);
+
+        let mut ball = baseline.ball;
+        ball.apply_delta(&delta.changed[0]);
+        assert_eq!(ball, current.ball);
+    }
+
+    #[test]
+    fn unknown_type_and_bad_enums_are_rejected() {
+        let mut buf = Vec::new();
+        rmp::encode::write_array_len(&mut buf, 1).unwrap();
+        rmp::encode::write_str(&mut buf, "Teleport").unwrap();
+        assert_eq!(ServerMessage::decode(&buf), Err(ProtocolError::UnknownType("Teleport".to_string())));
+
+        let mut buf = Vec::new();
+        rmp::encode::write_array_len(&mut buf, 3).unwrap();
+        rmp::encode::write_str(&mut buf, "MatchFound").unwrap();
+        rmp::encode::write_u32(&mut buf, 1).unwrap();
+        rmp::encode::write_u32(&mut buf, 2).unwrap();
+        assert_eq!(ServerMessage::decode(&buf), Err(ProtocolError::InvalidValue("team")));
+    }
+}
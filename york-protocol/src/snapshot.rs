@@ -0,0 +1,189 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{Capability, ClientId};
+
+/// Entity id used for the ball in delta snapshots; player ids are `ClientId`s.
+pub const BALL_ENTITY_ID: u32 = u32::MAX;
+
+// Fixed-point scales: positions to 0.1 px, velocities to 0.01 units
+pub const POSITION_SCALE: f64 = 10.0;
+pub const VELOCITY_SCALE: f64 = 100.0;
+
+// Bits of the per-entity field mask, in wire order
+pub const FIELD_X: u8 = 1 << 0;
+pub const FIELD_Y: u8 = 1 << 1;
+pub const FIELD_VEL_X: u8 = 1 << 2;
+pub const FIELD_VEL_Y: u8 = 1 << 3;
+pub const ALL_FIELDS: u8 = FIELD_X | FIELD_Y | FIELD_VEL_X | FIELD_VEL_Y;
+
+// Размеры поля: по ним считает физика сервера и PositionEncoding::Quantized
+// (совпадают с константами клиента client_with_wasm.js)
+pub const FIELD_WIDTH: f64 = 800.0;
+pub const FIELD_HEIGHT: f64 = 600.0;
+
+/// How `Snapshot` entities are written, chosen per client from the agreed
+/// capabilities. Readers tell the forms apart by their MessagePack type, so
+/// decoding needs no setting.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum PositionEncoding {
+    /// float64 everywhere, 9 bytes per number.
+    #[default]
+    Float64,
+    /// float32 everywhere, 5 bytes per number.
+    Float32,
+    /// Positions as i16 fixed-point relative to the field size (at most 3
+    /// bytes, ~0.02 px steps), velocities as float32.
+    Quantized,
+}
+
+impl PositionEncoding {
+    /// The most compact form both sides agreed on.
+    pub fn negotiate(capabilities: &[Capability]) -> Self {
+        if capabilities.contains(&Capability::QuantizedPositions) {
+            PositionEncoding::Quantized
+        } else if capabilities.contains(&Capability::Float32Positions) {
+            PositionEncoding::Float32
+        } else {
+            PositionEncoding::Float64
+        }
+    }
+}
+
+/// Fixed-point coordinate: `i16::MAX` steps per `size`, centered on the field
+/// so that `-size / 2..1.5 * size` fits; anything further out saturates.
+pub fn quantize_coord(value: f64, size: f64) -> i16 {
+    ((value - size / 2.0) * i16::MAX as f64 / size).round() as i16
+}
+
+pub fn dequantize_coord(value: i16, size: f64) -> f64 {
+    value as f64 * size / i16::MAX as f64 + size / 2.0
+}
+
+/// Entity state quantized to the wire representation: `[x, y, vel_x, vel_y]`.
+pub type QuantizedFields = [i32; 4];
+
+const FIELD_SCALES: [f64; 4] = [POSITION_SCALE, POSITION_SCALE, VELOCITY_SCALE, VELOCITY_SCALE];
+
+fn quantize(value: f64, scale: f64) -> i32 {
+    // `as` saturates and maps NaN to 0
+    (value * scale).round() as i32
+}
+
+/// Position and velocity of the ball or a player.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct EntityState {
+    pub x: f64,
+    pub y: f64,
+    pub vel_x: f64,
+    pub vel_y: f64,
+}
+
+impl EntityState {
+    pub fn quantized(&self) -> QuantizedFields {
+        let fields = [self.x, self.y, self.vel_x, self.vel_y];
+        let mut values = [0; 4];
+        for (i, value) in values.iter_mut().enumerate() {
+            *value = quantize(fields[i], FIELD_SCALES[i]);
+        }
+        values
+    }
+
+    /// Overwrites the fields selected by the delta's mask.
+    pub fn apply_delta(&mut self, delta: &EntityDelta) {
+        let fields = [&mut self.x, &mut self.y, &mut self.vel_x, &mut self.vel_y];
+        for (i, field) in fields.into_iter().enumerate() {
+            if delta.mask & (1 << i) != 0 {
+                *field = delta.values[i] as f64 / FIELD_SCALES[i];
+            }
+        }
+    }
+}
+
+/// State of the whole world at one tick, as sent to clients.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub tick: u32,
+    pub ball: EntityState,
+    // Отсортированы по id, чтобы порядок в сообщении был стабильным
+    pub players: Vec<(ClientId, EntityState)>,
+}
+
+impl Snapshot {
+    /// Ball first, then players in id order.
+    fn quantized_entities(&self) -> Vec<(u32, QuantizedFields)> {
+        let mut entities = Vec::with_capacity(self.players.len() + 1);
+        entities.push((BALL_ENTITY_ID, self.ball.quantized()));
+        for (id, player) in &self.players {
+            entities.push((*id, player.quantized()));
+        }
+        entities
+    }
+}
+
+/// Changed fields of one entity; only the values whose bit is set in `mask`
+/// are sent, the others are zero.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntityDelta {
+    pub id: u32,
+    pub mask: u8,
+    pub values: QuantizedFields,
+}
+
+impl EntityDelta {
+    /// Values selected by `mask`, in wire order.
+    pub fn masked_values(&self) -> impl Iterator<Item = i32> + '_ {
+        self.values
+            .iter()
+            .enumerate()
+            .filter(|(bit, _)| self.mask & (1 << bit) != 0)
+            .map(|(_, value)| *value)
+    }
+}
+
+/// Difference between a client's acknowledged snapshot and the current one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotDelta {
+    pub tick: u32,
+    pub baseline_tick: u32,
+    pub removed: Vec<u32>,
+    pub changed: Vec<EntityDelta>,
+}
+
+impl SnapshotDelta {
+    /// Added entities are sent with `ALL_FIELDS`, unchanged ones are omitted.
+    pub fn between(baseline: &Snapshot, current: &Snapshot) -> Self {
+        let old: HashMap<u32, QuantizedFields> = baseline.quantized_entities().into_iter().collect();
+        let new = current.quantized_entities();
+
+        let mut changed = Vec::new();
+        for (id, values) in &new {
+            let mask = match old.get(id) {
+                Some(old_values) => (0..4)
+                    .filter(|&i| old_values[i] != values[i])
+                    .fold(0, |mask, i| mask | (1 << i)),
+                None => ALL_FIELDS,
+            };
+            if mask != 0 {
+                // Поля вне маски не передаются, поэтому и здесь обнуляются
+                let mut masked = [0; 4];
+                for (i, value) in masked.iter_mut().enumerate() {
+                    if mask & (1 << i) != 0 {
+                        *value = values[i];
+                    }
+                }
+                changed.push(EntityDelta { id: *id, mask, values: masked });
+            }
+        }
+
+        let present: HashSet<u32> = new.iter().map(|(id, _)| *id).collect();
+        let mut removed: Vec<u32> = old.keys().copied().filter(|id| !present.contains(id)).collect();
+        removed.sort_unstable();
+
+        SnapshotDelta {
+            tick: current.tick,
+            baseline_tick: baseline.tick,
+            removed,
+            changed,
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::ProtocolError;
+
+/// Ext type of the MessagePack timestamp extension.
+pub const TIMESTAMP_EXT_TYPE: i8 = -1;
+
+/// Point in time as stored by the MessagePack timestamp extension: seconds
+/// since the Unix epoch (negative before 1970) plus nanoseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp {
+    pub seconds: i64,
+    /// Always below 1_000_000_000.
+    pub nanoseconds: u32,
+}
+
+impl Timestamp {
+    pub fn now() -> Self {
+        Timestamp::from(SystemTime::now())
+    }
+
+    /// Milliseconds since the epoch, as JS `Date` stores them; `None` for
+    /// NaN, ±Infinity and values outside the 96-bit range.
+    pub fn from_millis(millis: f64) -> Option<Self> {
+        if !millis.is_finite() {
+            return None;
+        }
+        let seconds = (millis / 1000.0).floor();
+        if seconds < i64::MIN as f64 || seconds >= i64::MAX as f64 {
+            return None;
+        }
+        let nanoseconds = (((millis - seconds * 1000.0) * 1_000_000.0).round() as u32).min(999_999_999);
+        Some(Timestamp { seconds: seconds as i64, nanoseconds })
+    }
+
+    /// Milliseconds since the epoch, as JS `Date` stores them.
+    pub fn as_millis(&self) -> f64 {
+        self.seconds as f64 * 1000.0 + self.nanoseconds as f64 / 1_000_000.0
+    }
+
+    /// Ext payload in the smallest form that holds the value: 32-bit for whole
+    /// seconds in 1970..2106, 64-bit up to 2514, 96-bit otherwise.
+    pub fn to_ext_data(&self) -> Vec<u8> {
+        if self.nanoseconds == 0 && (0..1 << 32).contains(&self.seconds) {
+            (self.seconds as u32).to_be_bytes().to_vec()
+        } else if (0..1 << 34).contains(&self.seconds) {
+            (((self.nanoseconds as u64) << 34) | self.seconds as u64).to_be_bytes().to_vec()
+        } else {
+            let mut data = Vec::with_capacity(12);
+            data.extend_from_slice(&self.nanoseconds.to_be_bytes());
+            data.extend_from_slice(&self.seconds.to_be_bytes());
+            data
+        }
+    }
+
+    /// Reads any of the three payload forms.
+    pub fn from_ext_data(data: &[u8]) -> Result<Self, ProtocolError> {
+        let timestamp = match *data {
+            [a, b, c, d] => Timestamp { seconds: u32::from_be_bytes([a, b, c, d]) as i64, nanoseconds: 0 },
+            [a, b, c, d, e, f, g, h] => {
+                let packed = u64::from_be_bytes([a, b, c, d, e, f, g, h]);
+                Timestamp { seconds: (packed & ((1 << 34) - 1)) as i64, nanoseconds: (packed >> 34) as u32 }
+            }
+            [a, b, c, d, ref seconds @ ..] if seconds.len() == 8 => Timestamp {
+                seconds: i64::from_be_bytes(seconds.try_into().unwrap()),
+                nanoseconds: u32::from_be_bytes([a, b, c, d]),
+            },
+            _ => return Err(ProtocolError::InvalidValue("timestamp")),
+        };
+        if timestamp.nanoseconds >= 1_000_000_000 {
+            return Err(ProtocolError::InvalidValue("timestamp"));
+        }
+        Ok(timestamp)
+    }
+
+    /// Writes the value as a complete ext (header and payload).
+    pub fn write(&self, buf: &mut Vec<u8>) -> Result<(), ProtocolError> {
+        let data = self.to_ext_data();
+        rmp::encode::write_ext_meta(buf, data.len() as u32, TIMESTAMP_EXT_TYPE)?;
+        buf.extend_from_slice(&data);
+        Ok(())
+    }
+
+    /// Reads a complete timestamp ext and advances `buf` past it.
+    pub fn read(buf: &mut &[u8]) -> Result<Self, ProtocolError> {
+        let meta = rmp::decode::read_ext_meta(buf).map_err(|e| ProtocolError::Decode(e.to_string()))?;
+        if meta.typeid != TIMESTAMP_EXT_TYPE {
+            return Err(ProtocolError::InvalidValue("timestamp"));
+        }
+        let len = meta.size as usize;
+        if buf.len() < len {
+            return Err(ProtocolError::Decode("timestamp data out of bounds".to_string()));
+        }
+        let (data, rest) = buf.split_at(len);
+        *buf = rest;
+        Timestamp::from_ext_data(data)
+    }
+}
+
+impl From<SystemTime> for Timestamp {
+    fn from(time: SystemTime) -> Self {
+        match time.duration_since(UNIX_EPOCH) {
+            Ok(since) => Timestamp { seconds: since.as_secs() as i64, nanoseconds: since.subsec_nanos() },
+            Err(e) => {
+                // До 1970: секунды округляются вниз, наносекунды остаются положительными
+                let before = e.duration();
+                // 2^63 секунд до эпохи - ровно i64::MIN, обычное отрицание переполнилось бы
+                let mut seconds = (before.as_secs() as i64).wrapping_neg();
+                let mut nanoseconds = 0;
+                if before.subsec_nanos() > 0 {
+                    seconds -= 1;
+                    nanoseconds = 1_000_000_000 - before.subsec_nanos();
+                }
+                Timestamp { seconds, nanoseconds }
+            }
+        }
+    }
+}
+
+/// Fails for nanoseconds of 1e9 and more and for times the platform's
+/// `SystemTime` can't hold.
+impl TryFrom<Timestamp> for SystemTime {
+    type Error = ProtocolError;
+
+    fn try_from(timestamp: Timestamp) -> Result<Self, Self::Error> {
+        if timestamp.nanoseconds >= 1_000_000_000 {
+            return Err(ProtocolError::InvalidValue("timestamp"));
+        }
+        let seconds = Duration::from_secs(timestamp.seconds.unsigned_abs());
+        let whole = if timestamp.seconds >= 0 {
+            UNIX_EPOCH.checked_add(seconds)
+        } else {
+            UNIX_EPOCH.checked_sub(seconds)
+        };
+        whole
+            .and_then(|time| time.checked_add(Duration::from_nanos(timestamp.nanoseconds as u64)))
+            .ok_or(ProtocolError::InvalidValue("timestamp"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timestamp(seconds: i64, nanoseconds: u32) -> Timestamp {
+        Timestamp { seconds, nanoseconds }
+    }
+
+    fn round_trip(timestamp: Timestamp) -> usize {
+        let data = timestamp.to_ext_data();
+        assert_eq!(Timestamp::from_ext_data(&data), Ok(timestamp));
+
+        let mut buf = Vec::new();
+        timestamp.write(&mut buf).unwrap();
+        let mut rest = buf.as_slice();
+        assert_eq!(Timestamp::read(&mut rest), Ok(timestamp));
+        assert!(rest.is_empty());
+        data.len()
+    }
+
+    #[test]
+    fn whole_seconds_up_to_2106_take_32_bits() {
+        assert_eq!(round_trip(timestamp(0, 0)), 4);
+        assert_eq!(round_trip(timestamp(1_700_000_000, 0)), 4);
+        assert_eq!(round_trip(timestamp((1 << 32) - 1, 0)), 4);
+    }
+
+    #[test]
+    fn nanoseconds_or_later_seconds_take_64_bits() {
+        assert_eq!(round_trip(timestamp(0, 1)), 8);
+        assert_eq!(round_trip(timestamp(1 << 32, 0)), 8);
+        assert_eq!(round_trip(timestamp((1 << 34) - 1, 999_999_999)), 8);
+    }
+
+    #[test]
+    fn negative_or_far_seconds_take_96_bits() {
+        assert_eq!(round_trip(timestamp(1 << 34, 0)), 12);
+        assert_eq!(round_trip(timestamp(-1, 500_000_000)), 12);
+        assert_eq!(round_trip(timestamp(i64::MIN, 0)), 12);
+        assert_eq!(round_trip(timestamp(i64::MAX, 999_999_999)), 12);
+    }
+
+    #[test]
+    fn nanoseconds_of_a_whole_second_are_rejected() {
+        let packed = (1_000_000_000u64 << 34) | 5;
+        assert!(Timestamp::from_ext_data(&packed.to_be_bytes()).is_err());
+
+        let mut data = 1_000_000_000u32.to_be_bytes().to_vec();
+        data.extend_from_slice(&5i64.to_be_bytes());
+        assert!(Timestamp::from_ext_data(&data).is_err());
+
+        assert!(SystemTime::try_from(timestamp(5, 1_000_000_000)).is_err());
+    }
+
+    #[test]
+    fn payloads_of_other_lengths_are_rejected() {
+        for len in [0, 1, 5, 9, 13] {
+            assert!(Timestamp::from_ext_data(&vec![0; len]).is_err(), "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn system_time_round_trip() {
+        let before_epoch = timestamp(-2, 250_000_000);
+        let time = SystemTime::try_from(before_epoch).unwrap();
+        assert_eq!(UNIX_EPOCH.duration_since(time).unwrap(), Duration::from_millis(1750));
+        assert_eq!(Timestamp::from(time), before_epoch);
+
+        let after_epoch = timestamp(1_700_000_000, 123_456_789);
+        assert_eq!(Timestamp::from(SystemTime::try_from(after_epoch).unwrap()), after_epoch);
+    }
+
+    #[test]
+    fn extreme_seconds_convert_or_fail_without_panicking() {
+        for extreme in [timestamp(i64::MIN, 0), timestamp(i64::MAX, 999_999_999)] {
+            if let Ok(time) = SystemTime::try_from(extreme) {
+                assert_eq!(Timestamp::from(time), extreme);
+            }
+        }
+    }
+}